@@ -1,21 +1,40 @@
 use std::error::Error;
 use std::path::Path;
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::HashMap, fmt, fs, path::PathBuf};
 
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
-use magnetite_core::state::AppState;
+use crate::scheduler::SchedulerConfig;
+use magnetite_core::{
+    access::AccessConfig,
+    http::{FetchConfig, ProxyConfig},
+    state::{AppState, Codec, Compression, Encoding, StoreLimits},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     logger_level: String,
     #[serde(skip)]
     config_path: PathBuf,
+    /// `http://`, `https://` or `socks5://` proxy for all outbound requests
     proxy: Option<String>,
+    /// hosts connected to directly, `example.com` also covers its subdomains
+    #[serde(default)]
+    no_proxy: Vec<String>,
     server: Server,
     cache: Cache,
+    #[serde(default)]
+    http: FetchConfig,
+    /// per host proxy overrides, e.g. `"www.gcores.com" = "socks5://127.0.0.1:1080"`
+    #[serde(default)]
+    site_proxy: HashMap<String, String>,
+    #[serde(default)]
+    scheduler: SchedulerConfig,
+    /// access key and signature of per feed codes, every endpoint is open without a key
+    #[serde(default)]
+    access: AccessConfig,
     #[serde(serialize_with = "toml::ser::tables_last")]
     env: HashMap<String, String>,
 }
@@ -38,14 +57,30 @@ impl Default for AppConfig {
             server: Server {
                 listen: "127.0.0.1".to_string(),
                 port: 8080,
+                admin_token: None,
             },
             cache: Cache {
                 expire: 5 * 60,
+                stale_expire: default_stale_expire(),
+                item_expire: default_item_expire(),
+                max_entries: 0,
+                max_bytes: 0,
+                sweep_interval: default_sweep_interval(),
                 redis_url: Some("redis://192.168.31.127:6380/1".to_string()),
+                path: None,
+                encoding: Default::default(),
+                compression: Default::default(),
                 r#type: CacheType::Redis {},
+                routes: Default::default(),
+                l1: Default::default(),
             },
             logger_level: "INFO".to_string(),
             proxy: None,
+            no_proxy: Vec::new(),
+            http: Default::default(),
+            site_proxy: Default::default(),
+            scheduler: Default::default(),
+            access: Default::default(),
             env: Default::default(),
             config_path: config_path().expect("can not find config file"),
         };
@@ -57,14 +92,52 @@ impl Default for AppConfig {
 
 impl AppConfig {
     pub fn into_state(self) -> AppState {
-        let redis = match &self.cache.r#type {
-            CacheType::Redis => Some(self.cache.redis_url.expect("redis url is missed")),
-            CacheType::Memory => None,
+        let (redis, disk) = match &self.cache.r#type {
+            CacheType::Redis => (
+                Some(self.cache.redis_url.expect("redis url is missed")),
+                None,
+            ),
+            CacheType::Memory => (None, None),
+            CacheType::Disk => (
+                None,
+                Some(self.cache.path.unwrap_or_else(default_cache_path)),
+            ),
         };
 
         AppState {
             redis,
+            disk,
             cache_expire: self.cache.expire,
+            route_expire: self.cache.routes,
+            stale_expire: self.cache.stale_expire,
+            item_expire: self.cache.item_expire,
+            limits: StoreLimits {
+                max_entries: self.cache.max_entries,
+                max_bytes: self.cache.max_bytes,
+                sweep_interval: self.cache.sweep_interval,
+            },
+            l1_expire: if self.cache.l1.enable {
+                self.cache.l1.expire
+            } else {
+                0
+            },
+            l1_limits: StoreLimits {
+                max_entries: self.cache.l1.max_entries,
+                max_bytes: self.cache.l1.max_bytes,
+                sweep_interval: self.cache.sweep_interval,
+            },
+            codec: Codec {
+                encoding: self.cache.encoding,
+                compression: self.cache.compression,
+            },
+            proxy: ProxyConfig {
+                url: self.proxy,
+                no_proxy: self.no_proxy,
+                sites: self.site_proxy,
+            },
+            fetch: self.http,
+            admin_token: self.server.admin_token,
+            access: self.access,
             env: self.env,
         }
     }
@@ -80,6 +153,10 @@ impl AppConfig {
         }
     }
 
+    pub fn scheduler(&self) -> SchedulerConfig {
+        self.scheduler.clone()
+    }
+
     pub fn address(&self) -> String {
         format!("{}:{}", self.server.listen, self.server.port)
     }
@@ -96,20 +173,109 @@ impl AppConfig {
 enum CacheType {
     Redis,
     Memory,
+    /// embedded sled database at `path`
+    Disk,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Cache {
     expire: usize,
+    /// seconds a stale feed keeps being served past `expire` while refreshes fail
+    #[serde(default = "default_stale_expire")]
+    stale_expire: usize,
+    #[serde(default = "default_item_expire")]
+    item_expire: usize,
+    /// memory and disk cache bounds, least recently used entries are evicted beyond, 0 for none
+    #[serde(default)]
+    max_entries: usize,
+    /// bytes
+    #[serde(default)]
+    max_bytes: usize,
+    /// seconds between removals of expired entries from the memory and disk caches, 0 to disable
+    #[serde(default = "default_sweep_interval")]
+    sweep_interval: u64,
     r#type: CacheType,
     #[serde(rename = "redis")]
     redis_url: Option<String>,
+    /// directory of the disk cache, defaults to the user cache directory
+    #[serde(default)]
+    path: Option<PathBuf>,
+    /// `json` or `msgpack`, entries written with another encoding stay readable
+    #[serde(default)]
+    encoding: Encoding,
+    /// `none`, `gzip` or `zstd`
+    #[serde(default)]
+    compression: Compression,
+    /// per route `expire` overrides, keyed by route pattern or feed path, e.g. `"/gcores/news" = 600`
+    #[serde(default)]
+    routes: HashMap<String, usize>,
+    /// in-process cache in front of the redis or disk cache
+    #[serde(default)]
+    l1: L1Cache,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct L1Cache {
+    enable: bool,
+    /// seconds, short so changes made by other instances show up soon
+    expire: usize,
+    max_entries: usize,
+    /// bytes
+    max_bytes: usize,
+}
+
+impl Default for L1Cache {
+    fn default() -> Self {
+        L1Cache {
+            enable: false,
+            expire: 30,
+            max_entries: 1000,
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+fn default_cache_path() -> PathBuf {
+    if let Some(proj_dirs) = ProjectDirs::from("com", "oiatz", "magnetite") {
+        proj_dirs.cache_dir().join("feeds")
+    } else {
+        Path::new("./cache").into()
+    }
+}
+
+fn default_stale_expire() -> usize {
+    24 * 60 * 60
+}
+
+fn default_item_expire() -> usize {
+    24 * 60 * 60
+}
+
+fn default_sweep_interval() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize)]
 struct Server {
     listen: String,
     port: u16,
+    /// bearer token of the `/api/admin` endpoints, they are disabled without one
+    admin_token: Option<String>,
+}
+
+// printed with the rest of the settings at startup
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("listen", &self.listen)
+            .field("port", &self.port)
+            .field(
+                "admin_token",
+                &self.admin_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
 }
 
 #[derive(Debug, StructOpt)]
@@ -1,11 +1,14 @@
+use actix::Actor;
 use actix_web::{web::Data, App, HttpServer};
 use simple_logger::SimpleLogger;
 use structopt::StructOpt;
 
 use app_config::{config_path, AppConfig, Opt};
-use magnetite_core::{scope, Cache};
+use magnetite_core::{api, registry, Access, Cache};
+use scheduler::Scheduler;
 
 mod app_config;
+mod scheduler;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -25,18 +28,29 @@ async fn main() -> std::io::Result<()> {
     eprintln!("settings = {:#?}", config);
 
     let addr = config.address();
+    let scheduler = config.scheduler();
 
     let app_state = config.into_state();
 
-    let storage = Data::new(app_state.storage().await);
+    let storage = app_state.storage().await;
+    let ctx = Data::new(app_state.context(storage));
     let app_state = Data::new(app_state);
+    let registry = Data::new(registry());
+
+    if scheduler.enable {
+        Scheduler::new(registry.clone(), ctx.clone(), scheduler).start();
+    }
 
     HttpServer::new(move || {
         App::new()
             .app_data(app_state.clone())
-            .app_data(storage.clone())
+            .app_data(ctx.clone())
+            .app_data(registry.clone())
             .wrap(Cache)
-            .service(scope())
+            // outermost, checked before anything is served from the cache or scraped
+            .wrap(Access)
+            .configure(api)
+            .configure(|cfg| registry.configure(cfg))
     })
     .bind(&addr)?
     .run()
@@ -0,0 +1,92 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use actix::{Actor, AsyncContext, Context, WrapFuture};
+use actix_web::web::Data;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use magnetite_core::{route::Context as RouteContext, Registry};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub enable: bool,
+    /// seconds since the last request during which a feed is kept warm
+    pub hot_window: u64,
+    /// feed paths refreshed at startup and on every tick, e.g. `/gcores/news`
+    pub prewarm: Vec<String>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            enable: false,
+            hot_window: 60 * 60,
+            prewarm: Vec::new(),
+        }
+    }
+}
+
+/// Refreshes recently requested and prewarmed feeds before their cache entries expire.
+pub struct Scheduler {
+    registry: Data<Registry>,
+    ctx: Data<RouteContext>,
+    config: SchedulerConfig,
+    interval: Duration,
+}
+
+impl Scheduler {
+    pub fn new(registry: Data<Registry>, ctx: Data<RouteContext>, config: SchedulerConfig) -> Self {
+        // tick at 3/4 of the shortest lifetime so every entry is replaced before it expires,
+        // `due` skips the feeds with a longer one
+        let shortest = registry.shortest_expire(&ctx) as u64;
+        let interval = Duration::from_secs((shortest * 3 / 4).max(10));
+        Scheduler {
+            registry,
+            ctx,
+            config,
+            interval,
+        }
+    }
+
+    fn refresh(&self, paths: BTreeSet<String>, ctx: &mut Context<Self>) {
+        for path in paths {
+            let registry = self.registry.clone();
+            let route_ctx = self.ctx.clone();
+            let interval = self.interval;
+            ctx.spawn(
+                async move {
+                    // feeds with a longer route ttl are still fresh at the next tick
+                    if !registry.due(&path, &route_ctx, interval).await {
+                        return;
+                    }
+                    match registry.refresh(&path, &route_ctx).await {
+                        Ok(()) => info!(target: "scheduler", "refreshed {}", path),
+                        Err(err) => warn!(target: "scheduler", "refresh {} failed: {}", path, err),
+                    }
+                }
+                .into_actor(self),
+            );
+        }
+    }
+}
+
+impl Actor for Scheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(target: "scheduler", "refreshing hot feeds every {:?}", self.interval);
+        self.refresh(self.config.prewarm.iter().cloned().collect(), ctx);
+
+        ctx.run_interval(self.interval, |act, ctx| {
+            let window = Duration::from_secs(act.config.hot_window);
+            let paths = act
+                .registry
+                .hot(window)
+                .into_iter()
+                .chain(act.config.prewarm.iter().cloned())
+                .collect();
+            act.refresh(paths, ctx);
+        });
+    }
+}
@@ -4,7 +4,7 @@ use actix::{
 };
 
 use crate::error::{Result, StorageError};
-use crate::store::Store;
+use crate::store::{CacheMetrics, KeyInfo, Store};
 use crate::{Key, Value};
 
 pub(crate) static CACHE_EXPIRE: usize = 5 * 60;
@@ -14,13 +14,26 @@ pub(crate) static CACHE_EXPIRE: usize = 5 * 60;
 pub enum StoreRequest {
     Get(Key),
     Set(Key, Value),
+    /// Set with a ttl in seconds instead of the store default
+    SetWithTtl(Key, Value, usize),
     Delete(Key),
+    /// Seconds until the key expires
+    Ttl(Key),
+    Metrics,
+    /// List keys starting with the prefix
+    Keys(Key),
+    /// Delete keys starting with the prefix
+    DeletePrefix(Key),
 }
 
 pub enum StoreResponse {
     Get(Result<Option<Value>>),
     Set(Result<()>),
     Delete(Result<()>),
+    Ttl(Result<Option<i64>>),
+    Metrics(Result<CacheMetrics>),
+    Keys(Result<Vec<KeyInfo>>),
+    DeletePrefix(Result<usize>),
 }
 
 impl<A: Actor> MessageResponse<A, StoreRequest> for StoreResponse {
@@ -52,6 +65,17 @@ where
         }
     }
 
+    async fn set_with_ttl(&self, key: Key, value: Value, ttl: usize) -> Result<()> {
+        match self
+            .send(StoreRequest::SetWithTtl(key, value, ttl))
+            .await
+            .map_err(StorageError::custom)?
+        {
+            StoreResponse::Set(val) => val,
+            _ => panic!(),
+        }
+    }
+
     async fn get(&self, key: Key) -> Result<Option<Value>> {
         match self
             .send(StoreRequest::Get(key))
@@ -73,4 +97,48 @@ where
             _ => panic!(),
         }
     }
+
+    async fn ttl(&self, key: Key) -> Result<Option<i64>> {
+        match self
+            .send(StoreRequest::Ttl(key))
+            .await
+            .map_err(StorageError::custom)?
+        {
+            StoreResponse::Ttl(val) => val,
+            _ => panic!(),
+        }
+    }
+
+    async fn metrics(&self) -> Result<CacheMetrics> {
+        match self
+            .send(StoreRequest::Metrics)
+            .await
+            .map_err(StorageError::custom)?
+        {
+            StoreResponse::Metrics(val) => val,
+            _ => panic!(),
+        }
+    }
+
+    async fn keys(&self, prefix: Key) -> Result<Vec<KeyInfo>> {
+        match self
+            .send(StoreRequest::Keys(prefix))
+            .await
+            .map_err(StorageError::custom)?
+        {
+            StoreResponse::Keys(val) => val,
+            _ => panic!(),
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize> {
+        match self
+            .send(StoreRequest::DeletePrefix(prefix))
+            .await
+            .map_err(StorageError::custom)?
+        {
+            StoreResponse::DeletePrefix(val) => val,
+            _ => panic!(),
+        }
+    }
 }
@@ -0,0 +1,183 @@
+use std::io::{Read, Write};
+
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, StorageError};
+
+/// Values smaller than this are stored uncompressed, the saving wouldn't pay for the cpu.
+const MIN_COMPRESS_SIZE: usize = 512;
+
+// header bytes have the high bit set, which never starts a json document
+const HEADER_FLAG: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Json = 0,
+    #[serde(rename = "msgpack")]
+    MessagePack = 1,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Json
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None = 0,
+    Gzip = 1,
+    Zstd = 2,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+/// How [`Storage`](crate::Storage) turns values into bytes.
+///
+/// Encoded values start with a header byte naming the encoding and compression, so entries
+/// written with another codec stay readable. Plain json is written without a header, which is
+/// the format of entries from before codecs existed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Codec {
+    pub encoding: Encoding,
+    pub compression: Compression,
+}
+
+impl Codec {
+    pub(crate) fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        let body = match self.encoding {
+            Encoding::Json => {
+                serde_json::to_vec(value).map_err(|_| StorageError::SerializationError)?
+            }
+            // named, so fields added with `#[serde(default)]` still read old entries
+            Encoding::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|_| StorageError::SerializationError)?
+            }
+        };
+        let compression = if body.len() < MIN_COMPRESS_SIZE {
+            Compression::None
+        } else {
+            self.compression
+        };
+        if self.encoding == Encoding::Json && compression == Compression::None {
+            return Ok(body);
+        }
+
+        let header = HEADER_FLAG | (self.encoding as u8) << 4 | compression as u8;
+        let mut bytes = vec![header];
+        match compression {
+            Compression::None => bytes.extend_from_slice(&body),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(bytes, flate2::Compression::default());
+                encoder
+                    .write_all(&body)
+                    .map_err(|_| StorageError::SerializationError)?;
+                bytes = encoder
+                    .finish()
+                    .map_err(|_| StorageError::SerializationError)?;
+            }
+            Compression::Zstd => {
+                let compressed = zstd::stream::encode_all(&body[..], 0)
+                    .map_err(|_| StorageError::SerializationError)?;
+                bytes.extend_from_slice(&compressed);
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decode a value written by any codec.
+pub(crate) fn decode<T>(bytes: &[u8]) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let header = match bytes.first() {
+        Some(header) if header & HEADER_FLAG != 0 => *header,
+        _ => return serde_json::from_slice(bytes).map_err(|_| StorageError::DeserializationError),
+    };
+
+    let body = &bytes[1..];
+    let body = match header & 0x0f {
+        0 => body.to_vec(),
+        1 => {
+            let mut decoder = flate2::read::GzDecoder::new(body);
+            let mut decoded = Vec::new();
+            decoder
+                .read_to_end(&mut decoded)
+                .map_err(|_| StorageError::DeserializationError)?;
+            decoded
+        }
+        2 => zstd::stream::decode_all(body).map_err(|_| StorageError::DeserializationError)?,
+        _ => return Err(StorageError::DeserializationError),
+    };
+    match (header >> 4) & 0x07 {
+        0 => serde_json::from_slice(&body).map_err(|_| StorageError::DeserializationError),
+        1 => rmp_serde::from_slice(&body).map_err(|_| StorageError::DeserializationError),
+        _ => Err(StorageError::DeserializationError),
+    }
+}
+
+#[cfg(test)]
+mod codec_test {
+    use serde::{Deserialize, Serialize};
+
+    use crate::codec::{decode, Codec, Compression, Encoding};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Channel {
+        title: String,
+        items: Vec<String>,
+    }
+
+    fn channel(items: usize) -> Channel {
+        Channel {
+            title: "news".to_string(),
+            items: (0..items)
+                .map(|i| format!("<p>article {}</p>", i))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        for &encoding in &[Encoding::Json, Encoding::MessagePack] {
+            for &compression in &[Compression::None, Compression::Gzip, Compression::Zstd] {
+                let codec = Codec {
+                    encoding,
+                    compression,
+                };
+                for &items in &[1, 100] {
+                    let bytes = codec.encode(&channel(items)).unwrap();
+                    assert_eq!(decode::<Channel>(&bytes).unwrap(), channel(items));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compress() {
+        let plain = serde_json::to_vec(&channel(100)).unwrap();
+        let codec = Codec {
+            encoding: Encoding::Json,
+            compression: Compression::Zstd,
+        };
+        assert!(codec.encode(&channel(100)).unwrap().len() < plain.len() / 2);
+        // too small to bother, stored as plain json
+        let small = codec.encode(&channel(1)).unwrap();
+        assert_eq!(small, serde_json::to_vec(&channel(1)).unwrap());
+    }
+
+    #[test]
+    fn legacy() {
+        let json = serde_json::to_vec(&channel(3)).unwrap();
+        assert_eq!(decode::<Channel>(&json).unwrap(), channel(3));
+        assert_eq!(Codec::default().encode(&channel(3)).unwrap(), json);
+    }
+}
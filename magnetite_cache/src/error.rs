@@ -10,6 +10,8 @@ use thiserror::Error;
 pub enum StorageError {
     #[error("RedisError: {0}")]
     RedisError(#[from] redis::RedisError),
+    #[error("SledError: {0}")]
+    SledError(#[from] sled::Error),
     #[error("StorageError: Method not supported for the storage backend provided")]
     MethodNotSupported,
     #[error("StorageError: Serialization failed")]
@@ -17,14 +19,14 @@ pub enum StorageError {
     #[error("StorageError: Deserialization failed")]
     DeserializationError,
     #[error("StorageError: {0}")]
-    Custom(Box<dyn Error + Send>),
+    Custom(Box<dyn Error + Send + Sync>),
 }
 
 impl StorageError {
     /// Shortcut method to construct Custom variant
     pub fn custom<E>(err: E) -> Self
     where
-        E: 'static + Error + Send,
+        E: 'static + Error + Send + Sync,
     {
         Self::Custom(Box::new(err))
     }
@@ -1,12 +1,15 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use actix::Actor;
 pub use redis::{ConnectionAddr, ConnectionInfo};
 
-pub use storage::Storage;
-use store::{dashmap::DashMapActor, redis::RedisActor};
+pub use codec::{Codec, Compression, Encoding};
+pub use storage::{Lookup, Storage};
+pub use store::{CacheMetrics, KeyInfo, StoreLimits};
+use store::{dashmap::DashMapActor, redis::RedisActor, sled::SledActor, tiered::TieredStore};
 
 mod actor;
+mod codec;
 pub mod error;
 mod storage;
 mod store;
@@ -15,10 +18,26 @@ type Key = Arc<[u8]>;
 type Value = Arc<[u8]>;
 
 pub fn dashmap_storage(cache_expire: usize) -> Storage {
-    let store = DashMapActor::new(cache_expire as i64).start(2);
+    bounded_dashmap_storage(cache_expire, Default::default())
+}
+
+pub fn bounded_dashmap_storage(cache_expire: usize, limits: StoreLimits) -> Storage {
+    let store = DashMapActor::new(cache_expire as i64).limits(limits).start(2);
     Storage::new(store)
 }
 
+pub fn sled_storage<P: AsRef<Path>>(
+    path: P,
+    cache_expire: usize,
+    limits: StoreLimits,
+) -> error::Result<Storage> {
+    let store = SledActor::open(path)?
+        .expire(cache_expire as i64)
+        .limits(limits)
+        .start(2);
+    Ok(Storage::new(store))
+}
+
 pub async fn redis_storage(url: String, cache_expire: usize) -> Storage {
     let store = RedisActor::new()
         .conn_info(url)
@@ -29,3 +48,9 @@ pub async fn redis_storage(url: String, cache_expire: usize) -> Storage {
         .start();
     Storage::new(store)
 }
+
+/// Put a bounded in-memory store with entries living at most `l1_ttl` seconds in front of `l2`.
+pub fn tiered_storage(l2: Storage, l1_ttl: usize, limits: StoreLimits) -> Storage {
+    let l1 = DashMapActor::new(l1_ttl as i64).limits(limits).start(2);
+    Storage::new(TieredStore::new(Arc::new(l1), l2.store, l1_ttl)).codec(l2.codec)
+}
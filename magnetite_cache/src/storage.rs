@@ -1,16 +1,35 @@
-use std::future::{ready, Ready};
 use std::sync::Arc;
 
-use actix_web::{dev::Payload, error::ErrorInternalServerError, FromRequest, HttpRequest};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    error::{Result, StorageError},
-    store::Store,
+    codec::{decode, Codec},
+    error::Result,
+    store::{CacheMetrics, KeyInfo, Store},
 };
 
+/// Value stored by [`Storage::set_stamped`], fresh until the utc timestamp `fresh_until`.
+#[derive(Serialize, Deserialize)]
+struct Stamped<V> {
+    fresh_until: i64,
+    value: V,
+}
+
+/// Value returned by [`Storage::get_stamped`].
+#[derive(Debug, PartialEq)]
+pub struct Lookup<V> {
+    pub value: V,
+    /// past its soft ttl, the caller should serve it and refresh it
+    pub stale: bool,
+    /// utc timestamp the value turns stale at
+    pub fresh_until: i64,
+}
+
 #[derive(Clone)]
 pub struct Storage {
-    store: Arc<dyn Store>,
+    pub(crate) store: Arc<dyn Store>,
+    pub(crate) codec: Codec,
 }
 
 impl Storage {
@@ -20,26 +39,70 @@ impl Storage {
     {
         Storage {
             store: Arc::new(store),
+            codec: Codec::default(),
         }
     }
 
+    /// Encode new values with `codec`, values written with any other codec stay readable.
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
     pub async fn set<K, V>(&self, key: K, value: &V) -> Result<()>
     where
         K: AsRef<[u8]>,
         V: serde::Serialize,
     {
         self.store
-            .set(key.as_ref().into(), serialize(value)?.into())
+            .set(key.as_ref().into(), self.codec.encode(value)?.into())
+            .await
+    }
+
+    pub async fn set_with_ttl<K, V>(&self, key: K, value: &V, ttl: usize) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: serde::Serialize,
+    {
+        self.store
+            .set_with_ttl(key.as_ref().into(), self.codec.encode(value)?.into(), ttl)
             .await
     }
 
+    /// Set a value that turns stale after `fresh` seconds and expires after `ttl` seconds.
+    pub async fn set_stamped<K, V>(&self, key: K, value: &V, fresh: usize, ttl: usize) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        V: serde::Serialize,
+    {
+        let stamped = Stamped {
+            fresh_until: Utc::now().timestamp() + fresh as i64,
+            value,
+        };
+        self.set_with_ttl(key, &stamped, ttl.max(fresh)).await
+    }
+
+    /// Get a value set by [`Storage::set_stamped`], stale values are returned until they expire.
+    pub async fn get_stamped<K, V>(&self, key: K) -> Result<Option<Lookup<V>>>
+    where
+        K: AsRef<[u8]>,
+        V: serde::de::DeserializeOwned,
+    {
+        let stamped: Option<Stamped<V>> = self.get(key).await?;
+        Ok(stamped.map(|stamped| Lookup {
+            stale: stamped.fresh_until <= Utc::now().timestamp(),
+            fresh_until: stamped.fresh_until,
+            value: stamped.value,
+        }))
+    }
+
     pub async fn get<K, V>(&self, key: K) -> Result<Option<V>>
     where
         K: AsRef<[u8]>,
         V: serde::de::DeserializeOwned,
     {
         let val = self.store.get(key.as_ref().into()).await?;
-        val.map(|val| deserialize(val.as_ref())).transpose()
+        val.map(|val| decode(val.as_ref())).transpose()
     }
 
     pub async fn delete<K>(&self, key: K) -> Result<()>
@@ -48,40 +111,29 @@ impl Storage {
     {
         self.store.delete(key.as_ref().into()).await
     }
-}
 
-impl FromRequest for Storage {
-    type Config = ();
-    type Error = actix_web::Error;
-    type Future = Ready<std::result::Result<Self, actix_web::Error>>;
-
-    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
-        if let Some(st) = req.app_data::<Storage>() {
-            ready(Ok(st.clone()))
-        } else {
-            log::debug!(
-                "Failed to construct Storage(actix-storage). \
-                 Request path: {:?}",
-                req.path(),
-            );
-            ready(Err(ErrorInternalServerError(
-                "Storage is not configured, please refer to actix-storage documentation\
-                for more information.",
-            )))
-        }
+    pub async fn metrics(&self) -> Result<CacheMetrics> {
+        self.store.metrics().await
     }
-}
 
-fn serialize<T>(value: &T) -> Result<Vec<u8>>
-where
-    T: serde::Serialize,
-{
-    serde_json::to_vec(value).map_err(|_| StorageError::SerializationError)
-}
+    /// Keys starting with `prefix`, with their remaining ttl and size.
+    pub async fn keys<K>(&self, prefix: K) -> Result<Vec<KeyInfo>>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.store.keys(prefix.as_ref().into()).await
+    }
+
+    /// Delete every key starting with `prefix`, returning how many were deleted.
+    pub async fn delete_prefix<K>(&self, prefix: K) -> Result<usize>
+    where
+        K: AsRef<[u8]>,
+    {
+        self.store.delete_prefix(prefix.as_ref().into()).await
+    }
 
-fn deserialize<T>(value: &[u8]) -> Result<T>
-where
-    T: serde::de::DeserializeOwned,
-{
-    serde_json::from_slice(value).map_err(|_| StorageError::DeserializationError)
+    /// Delete every key.
+    pub async fn flush(&self) -> Result<usize> {
+        self.delete_prefix("").await
+    }
 }
@@ -1,17 +1,160 @@
 pub mod dashmap;
 pub mod redis;
+pub mod sled;
+pub mod tiered;
+
+use std::time::Duration;
+
+use actix::{Actor, ActorContext, AsyncContext, Context, Message, Recipient};
+use serde::Serialize;
 
 use crate::{error::Result, Key, Value};
 
+/// Counters of a store, not every backend keeps them.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheMetrics {
+    pub entries: usize,
+    /// bytes of keys and values
+    pub bytes: usize,
+    /// entries dropped to stay within the size bounds
+    pub evictions: u64,
+    /// expired entries removed
+    pub expired: u64,
+}
+
+/// A key as listed by [`Store::keys`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyInfo {
+    pub key: String,
+    /// seconds until the entry expires, None if it never does
+    pub ttl: Option<i64>,
+    /// bytes of the stored value
+    pub size: usize,
+}
+
+/// Bounds of the local stores, 0 disables the respective bound.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StoreLimits {
+    pub max_entries: usize,
+    /// bytes of keys and values
+    pub max_bytes: usize,
+    /// seconds between removals of expired entries
+    pub sweep_interval: u64,
+}
+
+impl StoreLimits {
+    /// Bounds to evict down to once exceeded, 90% so a full store doesn't rescan on every insert.
+    pub(crate) fn low_watermark(&self) -> (usize, usize) {
+        let low = |limit: usize| {
+            if limit == 0 {
+                0
+            } else {
+                (limit - limit / 10).max(1)
+            }
+        };
+        (low(self.max_entries), low(self.max_bytes))
+    }
+}
+
+/// Removal of expired entries, which `Get` otherwise only hides until they are read.
+pub(crate) trait Sweep {
+    fn sweep(&self);
+}
+
+/// A local store bounded by [`StoreLimits`], see [`evict`].
+pub(crate) trait Evict: Sweep {
+    /// Entries and bytes of keys and values currently stored.
+    fn usage(&self) -> (usize, usize);
+
+    /// Every key, least recently used first.
+    fn lru_keys(&self) -> Result<Vec<Key>>;
+
+    /// Remove `key`, false if it is already gone.
+    fn evict_key(&self, key: &[u8]) -> Result<bool>;
+}
+
+/// Drop expired, then least recently used entries once `limits` are exceeded, returning how
+/// many entries were evicted.
+pub(crate) fn evict<T: Evict>(target: &T, limits: &StoreLimits) -> Result<u64> {
+    let within = |(max_entries, max_bytes): (usize, usize)| {
+        let (entries, bytes) = target.usage();
+        (max_entries == 0 || entries <= max_entries) && (max_bytes == 0 || bytes <= max_bytes)
+    };
+    if within((limits.max_entries, limits.max_bytes)) {
+        return Ok(0);
+    }
+    target.sweep();
+
+    let low_watermark = limits.low_watermark();
+    let mut evicted = 0;
+    for key in target.lru_keys()? {
+        if within(low_watermark) {
+            break;
+        }
+        if target.evict_key(&key)? {
+            evicted += 1;
+        }
+    }
+    Ok(evicted)
+}
+
+/// Asks a store actor to [`Sweep::sweep`], so sweeps run on the store's own threads.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub(crate) struct SweepRequest;
+
+/// Sends a [`SweepRequest`] to its store every `interval`.
+pub(crate) struct Sweeper {
+    target: Recipient<SweepRequest>,
+    interval: Duration,
+}
+
+impl Sweeper {
+    pub fn new(target: Recipient<SweepRequest>, interval: u64) -> Self {
+        Sweeper {
+            target,
+            interval: Duration::from_secs(interval),
+        }
+    }
+}
+
+impl Actor for Sweeper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, ctx| {
+            // the store is gone
+            if act.target.do_send(SweepRequest).is_err() {
+                ctx.stop();
+            }
+        });
+    }
+}
+
 /// Set of method for basic storage providers to implement.
 #[async_trait::async_trait]
 pub trait Store: Send + Sync {
     /// Set a key-value pair, if the key already exist, value should be overwritten
     async fn set(&self, key: Key, value: Value) -> Result<()>;
 
+    /// Like `set`, but the value expires after `ttl` seconds instead of the store default
+    async fn set_with_ttl(&self, key: Key, value: Value, ttl: usize) -> Result<()>;
+
     /// Get a value for specified key, it should result in None if the value does not exist
     async fn get(&self, key: Key) -> Result<Option<Value>>;
 
     /// Delete the key from storage, if the key doesn't exist, it shouldn't return an error
     async fn delete(&self, key: Key) -> Result<()>;
+
+    /// Seconds until the key expires, None if it doesn't exist or never expires
+    async fn ttl(&self, key: Key) -> Result<Option<i64>>;
+
+    /// Entry counts and eviction counters, if the backend keeps them
+    async fn metrics(&self) -> Result<CacheMetrics>;
+
+    /// Unexpired keys starting with `prefix`, an empty prefix lists every key
+    async fn keys(&self, prefix: Key) -> Result<Vec<KeyInfo>>;
+
+    /// Delete every key starting with `prefix`, returning how many were deleted
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize>;
 }
@@ -1,11 +1,17 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
 
 use actix::{Actor, Addr, Handler, SyncArbiter, SyncContext};
 use chrono::Utc;
 use dashmap::DashMap;
+use log::debug;
 
 use crate::{
     actor::{StoreRequest, StoreResponse, CACHE_EXPIRE},
+    error::Result,
+    store::{evict, CacheMetrics, Evict, KeyInfo, StoreLimits, Sweep, SweepRequest, Sweeper},
     Key, Value,
 };
 
@@ -13,23 +19,171 @@ use crate::{
 struct DashMapValue {
     bytes: Value,
     // utc tz
-    create_at: i64,
+    expire_at: i64,
+    // `Inner::tick` of the last read or write, for lru eviction
+    last_access: AtomicU64,
 }
 
 impl DashMapValue {
-    fn new(bytes: Value) -> Self {
-        DashMapValue {
+    fn size(&self, key: &Key) -> usize {
+        key.len() + self.bytes.len()
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expire_at <= now
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    map: DashMap<Key, DashMapValue>,
+    bytes: AtomicUsize,
+    tick: AtomicU64,
+    evictions: AtomicU64,
+    expired: AtomicU64,
+}
+
+impl Inner {
+    fn insert(&self, key: Key, bytes: Value, ttl: i64) {
+        let value = DashMapValue {
             bytes,
-            create_at: chrono::Utc::now().timestamp(),
+            expire_at: Utc::now().timestamp() + ttl,
+            last_access: AtomicU64::new(self.tick.fetch_add(1, Ordering::Relaxed)),
+        };
+        self.bytes.fetch_add(value.size(&key), Ordering::Relaxed);
+        if let Some(old) = self.map.insert(key.clone(), value) {
+            self.bytes.fetch_sub(old.size(&key), Ordering::Relaxed);
+        }
+    }
+
+    fn get(&self, key: &Key) -> Option<Value> {
+        let now = Utc::now().timestamp();
+        let value = self.map.get(key)?;
+        if value.is_expired(now) {
+            drop(value);
+            if self.remove_expired(key, now) {
+                self.expired.fetch_add(1, Ordering::Relaxed);
+            }
+            return None;
+        }
+        value
+            .last_access
+            .store(self.tick.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        Some(value.bytes.clone())
+    }
+
+    fn ttl(&self, key: &[u8]) -> Option<i64> {
+        let now = Utc::now().timestamp();
+        let value = self.map.get(key)?;
+        Some(value.expire_at - now).filter(|ttl| *ttl > 0)
+    }
+
+    fn remove(&self, key: &[u8]) -> bool {
+        match self.map.remove(key) {
+            Some((key, value)) => {
+                self.bytes.fetch_sub(value.size(&key), Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove `key` only if it is still expired, a concurrent set may have replaced it since
+    /// it was found expired.
+    fn remove_expired(&self, key: &Key, now: i64) -> bool {
+        match self.map.remove_if(key, |_, value| value.is_expired(now)) {
+            Some((key, value)) => {
+                self.bytes.fetch_sub(value.size(&key), Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn keys(&self, prefix: &[u8]) -> Vec<KeyInfo> {
+        let now = Utc::now().timestamp();
+        self.map
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix) && !entry.is_expired(now))
+            .map(|entry| KeyInfo {
+                key: String::from_utf8_lossy(entry.key()).into_owned(),
+                ttl: Some(entry.expire_at - now),
+                size: entry.bytes.len(),
+            })
+            .collect()
+    }
+
+    fn remove_prefix(&self, prefix: &[u8]) -> usize {
+        let keys = self
+            .map
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix))
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        keys.into_iter().filter(|key| self.remove(key)).count()
+    }
+
+    fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            entries: self.map.len(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Sweep for Inner {
+    fn sweep(&self) {
+        let now = Utc::now().timestamp();
+        let expired = self
+            .map
+            .iter()
+            .filter(|entry| entry.is_expired(now))
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        let removed = expired
+            .into_iter()
+            .filter(|key| self.remove_expired(key, now))
+            .count() as u64;
+        if removed > 0 {
+            self.expired.fetch_add(removed, Ordering::Relaxed);
+            debug!(target: "dashmap", "swept {} expired entries", removed);
         }
     }
 }
 
+impl Evict for Inner {
+    fn usage(&self) -> (usize, usize) {
+        (self.map.len(), self.bytes.load(Ordering::Relaxed))
+    }
+
+    fn lru_keys(&self) -> Result<Vec<Key>> {
+        let mut entries = self
+            .map
+            .iter()
+            .map(|entry| {
+                (
+                    entry.last_access.load(Ordering::Relaxed),
+                    entry.key().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(tick, _)| *tick);
+        Ok(entries.into_iter().map(|(_, key)| key).collect())
+    }
+
+    fn evict_key(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.remove(key))
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct DashMapActor {
-    map: Arc<DashMap<Key, DashMapValue>>,
+    inner: Arc<Inner>,
     // default: 5 * 60
     expire: i64,
+    limits: StoreLimits,
 }
 
 impl DashMapActor {
@@ -42,8 +196,12 @@ impl DashMapActor {
 
     pub fn with_capacity(capacity: usize) -> Self {
         DashMapActor {
-            map: DashMap::with_capacity(capacity).into(),
+            inner: Arc::new(Inner {
+                map: DashMap::with_capacity(capacity),
+                ..Default::default()
+            }),
             expire: CACHE_EXPIRE as i64,
+            limits: Default::default(),
         }
     }
 
@@ -52,13 +210,34 @@ impl DashMapActor {
         self
     }
 
+    pub fn limits(mut self, limits: StoreLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     pub fn start_default(threads: usize) -> Addr<Self> {
         let storage = DashMapActor::new(CACHE_EXPIRE as i64);
         SyncArbiter::start(threads, move || storage.clone())
     }
 
+    /// Start the store and, if `sweep_interval` is set, its sweeper on the current arbiter.
     pub fn start(self, threads: usize) -> Addr<Self> {
-        SyncArbiter::start(threads, move || self.clone())
+        let sweep_interval = self.limits.sweep_interval;
+        let addr = SyncArbiter::start(threads, move || self.clone());
+        if sweep_interval > 0 {
+            Sweeper::new(addr.clone().recipient(), sweep_interval).start();
+        }
+        addr
+    }
+
+    fn set(&self, key: Key, value: Value, ttl: i64) -> Result<()> {
+        self.inner.insert(key, value, ttl);
+        let evicted = evict(&*self.inner, &self.limits)?;
+        if evicted > 0 {
+            self.inner.evictions.fetch_add(evicted, Ordering::Relaxed);
+            debug!(target: "dashmap", "evicted {} entries", evicted);
+        }
+        Ok(())
     }
 }
 
@@ -71,39 +250,43 @@ impl Handler<StoreRequest> for DashMapActor {
 
     fn handle(&mut self, msg: StoreRequest, _: &mut Self::Context) -> Self::Result {
         match msg {
-            StoreRequest::Set(key, value) => {
-                self.map
-                    .entry(key)
-                    .and_modify(|val| {
-                        val.bytes = value.clone();
-                        val.create_at = chrono::Utc::now().timestamp();
-                    })
-                    .or_insert_with(|| DashMapValue::new(value));
-                StoreResponse::Set(Ok(()))
-            }
-            StoreRequest::Get(key) => {
-                let value = self.map.get(&key).map_or(None, |val| {
-                    if val.create_at + self.expire > Utc::now().timestamp() {
-                        Some(val.bytes.clone())
-                    } else {
-                        None
-                    }
-                });
-                StoreResponse::Get(Ok(value))
+            StoreRequest::Set(key, value) => StoreResponse::Set(self.set(key, value, self.expire)),
+            StoreRequest::SetWithTtl(key, value, ttl) => {
+                StoreResponse::Set(self.set(key, value, ttl as i64))
             }
+            StoreRequest::Get(key) => StoreResponse::Get(Ok(self.inner.get(&key))),
             StoreRequest::Delete(key) => {
-                self.map.remove(&key);
+                self.inner.remove(&key);
                 StoreResponse::Delete(Ok(()))
             }
+            StoreRequest::Ttl(key) => StoreResponse::Ttl(Ok(self.inner.ttl(&key))),
+            StoreRequest::Metrics => StoreResponse::Metrics(Ok(self.inner.metrics())),
+            StoreRequest::Keys(prefix) => StoreResponse::Keys(Ok(self.inner.keys(&prefix))),
+            StoreRequest::DeletePrefix(prefix) => {
+                StoreResponse::DeletePrefix(Ok(self.inner.remove_prefix(&prefix)))
+            }
         }
     }
 }
 
+impl Handler<SweepRequest> for DashMapActor {
+    type Result = ();
+
+    fn handle(&mut self, _: SweepRequest, _: &mut Self::Context) {
+        self.inner.sweep();
+    }
+}
+
 #[cfg(test)]
 mod dashmap_test {
+    use std::time::Duration;
+
+    use actix_rt::time::sleep;
+
     use crate::error::Result;
-    use crate::storage::Storage;
-    use crate::store::dashmap::DashMapActor;
+    use crate::storage::{Lookup, Storage};
+    use crate::store::dashmap::{DashMapActor, Inner, StoreLimits};
+    use crate::Key;
 
     #[test]
     fn test() {
@@ -126,4 +309,155 @@ mod dashmap_test {
             assert_eq!(get_res.unwrap(), None);
         });
     }
+
+    #[test]
+    fn ttl_test() {
+        let system = actix_rt::System::new();
+        let store = system.block_on(async { DashMapActor::new(600).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let value = "value".to_string();
+
+            assert!(storage.set_with_ttl("short", &value, 1).await.is_ok());
+            assert!(storage.set_with_ttl("long", &value, 600).await.is_ok());
+            sleep(Duration::from_secs(2)).await;
+
+            let get_res: Result<Option<String>> = storage.get("short").await;
+            assert_eq!(get_res.unwrap(), None);
+            let get_res: Result<Option<String>> = storage.get("long").await;
+            assert_eq!(get_res.unwrap(), Some(value));
+        });
+    }
+
+    #[test]
+    fn stale_test() {
+        let system = actix_rt::System::new();
+        let store = system.block_on(async { DashMapActor::new(600).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let value = "value".to_string();
+
+            assert!(storage.set_stamped("key", &value, 2, 5).await.is_ok());
+            let get_res: Result<Option<Lookup<String>>> = storage.get_stamped("key").await;
+            let lookup = get_res.unwrap().unwrap();
+            assert_eq!(lookup.value, value);
+            assert!(!lookup.stale);
+
+            sleep(Duration::from_secs(3)).await;
+            let get_res: Result<Option<Lookup<String>>> = storage.get_stamped("key").await;
+            let lookup = get_res.unwrap().unwrap();
+            assert_eq!(lookup.value, value);
+            assert!(lookup.stale);
+
+            sleep(Duration::from_secs(3)).await;
+            let get_res: Result<Option<Lookup<String>>> = storage.get_stamped("key").await;
+            assert_eq!(get_res.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn lru_test() {
+        let system = actix_rt::System::new();
+        let limits = StoreLimits {
+            max_entries: 10,
+            ..Default::default()
+        };
+        let store = system.block_on(async { DashMapActor::new(600).limits(limits).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            for i in 0..10 {
+                assert!(storage.set(format!("key{}", i), &i).await.is_ok());
+            }
+            // key0 becomes the most recently used
+            let get_res: Result<Option<i32>> = storage.get("key0").await;
+            assert_eq!(get_res.unwrap(), Some(0));
+            assert!(storage.set("key10", &10).await.is_ok());
+
+            let get_res: Result<Option<i32>> = storage.get("key0").await;
+            assert_eq!(get_res.unwrap(), Some(0));
+            for key in &["key1", "key2"] {
+                let get_res: Result<Option<i32>> = storage.get(key).await;
+                assert_eq!(get_res.unwrap(), None);
+            }
+            let metrics = storage.metrics().await.unwrap();
+            assert_eq!(metrics.entries, 9);
+            assert_eq!(metrics.evictions, 2);
+        });
+    }
+
+    #[test]
+    fn sweep_test() {
+        let system = actix_rt::System::new();
+        let limits = StoreLimits {
+            sweep_interval: 1,
+            ..Default::default()
+        };
+        let store = system.block_on(async { DashMapActor::new(600).limits(limits).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let value = "value".to_string();
+
+            assert!(storage.set_with_ttl("short", &value, 1).await.is_ok());
+            assert!(storage.set("long", &value).await.is_ok());
+            sleep(Duration::from_secs(3)).await;
+
+            let metrics = storage.metrics().await.unwrap();
+            assert_eq!(metrics.entries, 1);
+            assert_eq!(metrics.expired, 1);
+            assert_eq!(metrics.bytes, "long".len() + "\"value\"".len());
+        });
+    }
+
+    #[test]
+    fn prefix_test() {
+        let system = actix_rt::System::new();
+        let store = system.block_on(async { DashMapActor::new(600).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let value = "value".to_string();
+            assert!(storage.set("feed:/a", &value).await.is_ok());
+            assert!(storage.set("feed:/b", &value).await.is_ok());
+            assert!(storage.set_with_ttl("render:/a", &value, 60).await.is_ok());
+
+            let mut keys = storage.keys("feed:").await.unwrap();
+            keys.sort_by(|a, b| a.key.cmp(&b.key));
+            assert_eq!(
+                keys.iter()
+                    .map(|info| info.key.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["feed:/a", "feed:/b"]
+            );
+            assert_eq!(keys[0].size, "\"value\"".len());
+            assert!(keys[0].ttl.unwrap() > 590);
+            let keys = storage.keys("render:").await.unwrap();
+            assert!(keys[0].ttl.unwrap() <= 60);
+
+            assert_eq!(storage.delete_prefix("feed:").await.unwrap(), 2);
+            assert_eq!(storage.keys("").await.unwrap().len(), 1);
+            assert_eq!(storage.flush().await.unwrap(), 1);
+            assert_eq!(storage.metrics().await.unwrap().entries, 0);
+        });
+    }
+
+    #[test]
+    fn remove_expired_test() {
+        let inner = Inner::default();
+        let key: Key = b"key"[..].into();
+        inner.insert(key.clone(), b"old"[..].into(), -1);
+        let now = chrono::Utc::now().timestamp();
+        // a set lands between finding the entry expired and removing it
+        inner.insert(key.clone(), b"new"[..].into(), 600);
+
+        assert!(!inner.remove_expired(&key, now));
+        assert_eq!(inner.get(&key), Some(b"new"[..].into()));
+        inner.insert(key.clone(), b"old"[..].into(), -1);
+        assert!(inner.remove_expired(&key, now));
+        assert_eq!(inner.metrics().entries, 0);
+        assert_eq!(inner.metrics().bytes, 0);
+    }
 }
@@ -4,10 +4,14 @@ use redis::{aio::ConnectionManager, AsyncCommands, IntoConnectionInfo};
 use crate::{
     actor::{StoreRequest, StoreResponse, CACHE_EXPIRE},
     error::{Result, StorageError},
+    store::KeyInfo,
 };
 
 const SCOPE: [u8; 9] = *b"RSS_CACHE";
 
+/// Keys `SCAN` is asked for per round trip.
+const SCAN_COUNT: usize = 500;
+
 fn get_full_key<K>(key: K) -> Vec<u8>
 where
     K: AsRef<[u8]>,
@@ -15,6 +19,75 @@ where
     [SCOPE.as_ref(), b":", key.as_ref()].concat()
 }
 
+/// `MATCH` pattern of the keys starting with `prefix`, glob characters in it are escaped.
+fn prefix_pattern(prefix: &[u8]) -> Vec<u8> {
+    let mut pattern = get_full_key(b"");
+    for &byte in prefix {
+        if b"*?[]\\".contains(&byte) {
+            pattern.push(b'\\');
+        }
+        pattern.push(byte);
+    }
+    pattern.push(b'*');
+    pattern
+}
+
+/// Full keys starting with `prefix`, `SCAN` doesn't block the server like `KEYS` does.
+async fn scan(conn: &mut ConnectionManager, prefix: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let pattern = prefix_pattern(prefix);
+    let mut cursor = 0u64;
+    let mut keys = Vec::new();
+    loop {
+        let (next, mut batch): (u64, Vec<Vec<u8>>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(&pattern[..])
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query_async(conn)
+            .await?;
+        keys.append(&mut batch);
+        if next == 0 {
+            return Ok(keys);
+        }
+        cursor = next;
+    }
+}
+
+async fn keys(conn: &mut ConnectionManager, prefix: &[u8]) -> Result<Vec<KeyInfo>> {
+    let keys = scan(conn, prefix).await?;
+    let mut infos = Vec::with_capacity(keys.len());
+    for chunk in keys.chunks(SCAN_COUNT) {
+        let mut pipe = redis::pipe();
+        for key in chunk {
+            pipe.cmd("TTL").arg(&key[..]).cmd("STRLEN").arg(&key[..]);
+        }
+        let stats: Vec<i64> = pipe.query_async(conn).await?;
+        for (key, stat) in chunk.iter().zip(stats.chunks(2)) {
+            // -2: deleted since the scan
+            if stat[0] == -2 {
+                continue;
+            }
+            infos.push(KeyInfo {
+                key: String::from_utf8_lossy(&key[SCOPE.len() + 1..]).into_owned(),
+                ttl: if stat[0] >= 0 { Some(stat[0]) } else { None },
+                size: stat[1] as usize,
+            });
+        }
+    }
+    Ok(infos)
+}
+
+async fn delete_prefix(conn: &mut ConnectionManager, prefix: &[u8]) -> Result<usize> {
+    let keys = scan(conn, prefix).await?;
+    let mut deleted = 0;
+    for chunk in keys.chunks(SCAN_COUNT) {
+        let count: usize = conn.del(chunk).await?;
+        deleted += count;
+    }
+    Ok(deleted)
+}
+
 #[derive(Clone)]
 pub struct RedisActor {
     conn: ConnectionManager,
@@ -94,6 +167,11 @@ async fn msg_handle(
             let res = conn.set_ex(full_key, value.as_ref(), expire).await;
             StoreResponse::Set(res.map_err(|err| StorageError::RedisError(err)))
         }
+        StoreRequest::SetWithTtl(key, value, ttl) => {
+            let full_key = get_full_key(key);
+            let res = conn.set_ex(full_key, value.as_ref(), ttl).await;
+            StoreResponse::Set(res.map_err(|err| StorageError::RedisError(err)))
+        }
         StoreRequest::Get(key) => {
             let full_key = get_full_key(key);
             let res = conn.get(full_key).await;
@@ -113,6 +191,21 @@ async fn msg_handle(
             let res = conn.del(full_key).await;
             StoreResponse::Delete(res.map_err(|err| StorageError::RedisError(err)))
         }
+        StoreRequest::Ttl(key) => {
+            let full_key = get_full_key(key);
+            // -2 if the key doesn't exist, -1 if it never expires
+            let res = conn.ttl(full_key).await;
+            StoreResponse::Ttl(
+                res.map(|ttl: i64| Some(ttl).filter(|ttl| *ttl > 0))
+                    .map_err(|err| StorageError::RedisError(err)),
+            )
+        }
+        // redis enforces its own `maxmemory-policy`
+        StoreRequest::Metrics => StoreResponse::Metrics(Err(StorageError::MethodNotSupported)),
+        StoreRequest::Keys(prefix) => StoreResponse::Keys(keys(&mut conn, &prefix).await),
+        StoreRequest::DeletePrefix(prefix) => {
+            StoreResponse::DeletePrefix(delete_prefix(&mut conn, &prefix).await)
+        }
     }
 }
 
@@ -125,7 +218,7 @@ mod redis_test {
     use redis::{ConnectionAddr, ConnectionInfo};
 
     use crate::error::Result;
-    use crate::storage::Storage;
+    use crate::storage::{Lookup, Storage};
     use crate::store::redis::RedisActor;
 
     #[test]
@@ -162,4 +255,67 @@ mod redis_test {
             assert_eq!(get_res.unwrap(), None);
         });
     }
+
+    #[test]
+    fn ttl_test() {
+        let system = actix_rt::System::new();
+        let store = system.block_on(async {
+            let redis = RedisActor::new()
+                .conn_info("redis://192.168.31.127:6380/1".parse().unwrap())
+                .expire(1)
+                .finish()
+                .await
+                .unwrap();
+            redis.start()
+        });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let key = "ttl";
+            let value = "value".to_string();
+
+            // outlives the store default of 1 second
+            assert!(storage.set_with_ttl(key, &value, 600).await.is_ok());
+            sleep(Duration::from_secs(2)).await;
+            let get_res: Result<Option<String>> = storage.get(key).await;
+            assert_eq!(get_res.unwrap(), Some(value));
+            assert!(storage.delete(key).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn stale_test() {
+        let system = actix_rt::System::new();
+        let store = system.block_on(async {
+            let redis = RedisActor::new()
+                .conn_info("redis://192.168.31.127:6380/1".parse().unwrap())
+                .expire(1)
+                .finish()
+                .await
+                .unwrap();
+            redis.start()
+        });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let key = "stale";
+            let value = "value".to_string();
+
+            assert!(storage.set_stamped(key, &value, 2, 5).await.is_ok());
+            let get_res: Result<Option<Lookup<String>>> = storage.get_stamped(key).await;
+            let lookup = get_res.unwrap().unwrap();
+            assert_eq!(lookup.value, value);
+            assert!(!lookup.stale);
+
+            sleep(Duration::from_secs(3)).await;
+            let get_res: Result<Option<Lookup<String>>> = storage.get_stamped(key).await;
+            let lookup = get_res.unwrap().unwrap();
+            assert_eq!(lookup.value, value);
+            assert!(lookup.stale);
+
+            sleep(Duration::from_secs(3)).await;
+            let get_res: Result<Option<Lookup<String>>> = storage.get_stamped(key).await;
+            assert_eq!(get_res.unwrap(), None);
+        });
+    }
 }
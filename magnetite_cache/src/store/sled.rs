@@ -0,0 +1,509 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use actix::{Actor, Addr, Handler, SyncArbiter, SyncContext};
+use chrono::Utc;
+use log::{debug, error};
+use sled::{Db, IVec, Tree};
+
+use crate::{
+    actor::{StoreRequest, StoreResponse, CACHE_EXPIRE},
+    error::Result,
+    store::{evict, CacheMetrics, Evict, KeyInfo, StoreLimits, Sweep, SweepRequest, Sweeper},
+    Key, Value,
+};
+
+const EXPIRE_LEN: usize = 8;
+
+/// `expire_at` as a big endian utc timestamp, followed by the value
+fn encode(value: &[u8], expire_at: i64) -> Vec<u8> {
+    [&expire_at.to_be_bytes()[..], value].concat()
+}
+
+fn expire_at(raw: &[u8]) -> i64 {
+    let mut buf = [0; EXPIRE_LEN];
+    buf.copy_from_slice(&raw[..EXPIRE_LEN]);
+    i64::from_be_bytes(buf)
+}
+
+fn size(key: &[u8], raw: &[u8]) -> usize {
+    key.len() + raw.len() - EXPIRE_LEN
+}
+
+/// Key of the expiry index, ordered by `expire_at` so expired keys are a range scan away
+fn expiry_key(key: &[u8], expire_at: i64) -> Vec<u8> {
+    [&expire_at.to_be_bytes()[..], key].concat()
+}
+
+struct Inner {
+    db: Db,
+    /// key -> encoded value
+    data: Tree,
+    /// key -> big endian tick of the last read or write, for lru eviction
+    access: Tree,
+    /// `expire_at` followed by the key -> nothing, see [`expiry_key`]
+    expiry: Tree,
+    entries: AtomicUsize,
+    bytes: AtomicUsize,
+    evictions: AtomicU64,
+    expired: AtomicU64,
+}
+
+impl Inner {
+    fn new(db: Db) -> Result<Self> {
+        let data = db.open_tree("data")?;
+        let access = db.open_tree("access")?;
+        let expiry = db.open_tree("expiry")?;
+        let (mut entries, mut bytes) = (0, 0);
+        for item in data.iter() {
+            let (key, raw) = item?;
+            entries += 1;
+            bytes += size(&key, &raw);
+        }
+        Ok(Inner {
+            db,
+            data,
+            access,
+            expiry,
+            entries: AtomicUsize::new(entries),
+            bytes: AtomicUsize::new(bytes),
+            evictions: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+        })
+    }
+
+    fn touch(&self, key: &[u8]) -> Result<()> {
+        let tick = self.db.generate_id()?;
+        self.access.insert(key, &tick.to_be_bytes()[..])?;
+        Ok(())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8], ttl: i64) -> Result<()> {
+        let expire = Utc::now().timestamp() + ttl;
+        let raw = encode(value, expire);
+        self.bytes.fetch_add(size(key, &raw), Ordering::Relaxed);
+        self.expiry.insert(expiry_key(key, expire), &[])?;
+        match self.data.insert(key, raw)? {
+            Some(old) => {
+                self.bytes.fetch_sub(size(key, &old), Ordering::Relaxed);
+                if expire_at(&old) != expire {
+                    self.expiry.remove(expiry_key(key, expire_at(&old)))?;
+                }
+            }
+            None => {
+                self.entries.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.touch(key)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        let raw = match self.data.get(key)? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        if expire_at(&raw) <= Utc::now().timestamp() {
+            if self.remove_expired(key, &raw)? {
+                self.expired.fetch_add(1, Ordering::Relaxed);
+            }
+            return Ok(None);
+        }
+        self.touch(key)?;
+        Ok(Some(raw[EXPIRE_LEN..].into()))
+    }
+
+    fn ttl(&self, key: &[u8]) -> Result<Option<i64>> {
+        let now = Utc::now().timestamp();
+        let raw = self.data.get(key)?;
+        Ok(raw.map(|raw| expire_at(&raw) - now).filter(|ttl| *ttl > 0))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<bool> {
+        self.access.remove(key)?;
+        match self.data.remove(key)? {
+            Some(raw) => {
+                self.expiry.remove(expiry_key(key, expire_at(&raw)))?;
+                self.entries.fetch_sub(1, Ordering::Relaxed);
+                self.bytes.fetch_sub(size(key, &raw), Ordering::Relaxed);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Remove `key` only if it still holds the expired `raw`, a concurrent set may have
+    /// replaced it since it was read.
+    fn remove_expired(&self, key: &[u8], raw: &IVec) -> Result<bool> {
+        if self
+            .data
+            .compare_and_swap(key, Some(raw), None::<IVec>)?
+            .is_err()
+        {
+            return Ok(false);
+        }
+        self.access.remove(key)?;
+        self.expiry.remove(expiry_key(key, expire_at(raw)))?;
+        self.entries.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(size(key, raw), Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Remove the entry indexed at `index`, or just the index entry if the key was removed or
+    /// written with another expiry since.
+    fn sweep_index(&self, index: &[u8], now: i64) -> Result<bool> {
+        let (expire, key) = (expire_at(index), &index[EXPIRE_LEN..]);
+        let removed = match self.data.get(key)? {
+            Some(raw) if expire_at(&raw) == expire && expire <= now => {
+                self.remove_expired(key, &raw)?
+            }
+            _ => false,
+        };
+        self.expiry.remove(index)?;
+        Ok(removed)
+    }
+
+    fn keys(&self, prefix: &[u8]) -> Result<Vec<KeyInfo>> {
+        let now = Utc::now().timestamp();
+        let mut keys = Vec::new();
+        for item in self.data.scan_prefix(prefix) {
+            let (key, raw) = item?;
+            if expire_at(&raw) <= now {
+                continue;
+            }
+            keys.push(KeyInfo {
+                key: String::from_utf8_lossy(&key).into_owned(),
+                ttl: Some(expire_at(&raw) - now),
+                size: raw.len() - EXPIRE_LEN,
+            });
+        }
+        Ok(keys)
+    }
+
+    fn remove_prefix(&self, prefix: &[u8]) -> Result<usize> {
+        let keys = self
+            .data
+            .scan_prefix(prefix)
+            .keys()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let mut removed = 0;
+        for key in keys {
+            if self.remove(&key)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            entries: self.entries.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Sweep for Inner {
+    fn sweep(&self) {
+        let now = Utc::now().timestamp();
+        // every index key up to `now` inclusive, whatever key follows it
+        let expired = self
+            .expiry
+            .range(..&(now + 1).to_be_bytes()[..])
+            .keys()
+            .filter_map(|index| index.ok())
+            .collect::<Vec<_>>();
+        let mut removed = 0;
+        for index in expired {
+            match self.sweep_index(&index, now) {
+                Ok(true) => removed += 1,
+                Ok(false) => {}
+                Err(err) => error!(target: "sled", "sweep failed: {}", err),
+            }
+        }
+        if removed > 0 {
+            self.expired.fetch_add(removed, Ordering::Relaxed);
+            debug!(target: "sled", "swept {} expired entries", removed);
+        }
+    }
+}
+
+impl Evict for Inner {
+    fn usage(&self) -> (usize, usize) {
+        (
+            self.entries.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    fn lru_keys(&self) -> Result<Vec<Key>> {
+        let mut entries = self
+            .access
+            .iter()
+            .map(|item| {
+                let (key, tick) = item?;
+                let mut buf = [0; 8];
+                buf.copy_from_slice(&tick);
+                Ok((u64::from_be_bytes(buf), key))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_unstable_by_key(|(tick, _)| *tick);
+        Ok(entries
+            .into_iter()
+            .map(|(_, key)| key.as_ref().into())
+            .collect())
+    }
+
+    fn evict_key(&self, key: &[u8]) -> Result<bool> {
+        self.remove(key)
+    }
+}
+
+/// Store persisted to an embedded sled database, survives restarts without an external service.
+#[derive(Clone)]
+pub struct SledActor {
+    inner: Arc<Inner>,
+    // default: 5 * 60
+    expire: i64,
+    limits: StoreLimits,
+}
+
+impl SledActor {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(SledActor {
+            inner: Arc::new(Inner::new(sled::open(path)?)?),
+            expire: CACHE_EXPIRE as i64,
+            limits: Default::default(),
+        })
+    }
+
+    pub fn expire(mut self, expire: i64) -> Self {
+        self.expire = expire;
+        self
+    }
+
+    pub fn limits(mut self, limits: StoreLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Start the store and, if `sweep_interval` is set, its sweeper on the current arbiter.
+    pub fn start(self, threads: usize) -> Addr<Self> {
+        let sweep_interval = self.limits.sweep_interval;
+        let addr = SyncArbiter::start(threads, move || self.clone());
+        if sweep_interval > 0 {
+            Sweeper::new(addr.clone().recipient(), sweep_interval).start();
+        }
+        addr
+    }
+
+    fn set(&self, key: Key, value: Value, ttl: i64) -> Result<()> {
+        self.inner.insert(&key, &value, ttl)?;
+        let evicted = evict(&*self.inner, &self.limits)?;
+        if evicted > 0 {
+            self.inner.evictions.fetch_add(evicted, Ordering::Relaxed);
+            debug!(target: "sled", "evicted {} entries", evicted);
+        }
+        Ok(())
+    }
+}
+
+impl Actor for SledActor {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<StoreRequest> for SledActor {
+    type Result = StoreResponse;
+
+    fn handle(&mut self, msg: StoreRequest, _: &mut Self::Context) -> Self::Result {
+        match msg {
+            StoreRequest::Set(key, value) => StoreResponse::Set(self.set(key, value, self.expire)),
+            StoreRequest::SetWithTtl(key, value, ttl) => {
+                StoreResponse::Set(self.set(key, value, ttl as i64))
+            }
+            StoreRequest::Get(key) => StoreResponse::Get(self.inner.get(&key)),
+            StoreRequest::Delete(key) => StoreResponse::Delete(self.inner.remove(&key).map(|_| ())),
+            StoreRequest::Ttl(key) => StoreResponse::Ttl(self.inner.ttl(&key)),
+            StoreRequest::Metrics => StoreResponse::Metrics(Ok(self.inner.metrics())),
+            StoreRequest::Keys(prefix) => StoreResponse::Keys(self.inner.keys(&prefix)),
+            StoreRequest::DeletePrefix(prefix) => {
+                StoreResponse::DeletePrefix(self.inner.remove_prefix(&prefix))
+            }
+        }
+    }
+}
+
+impl Handler<SweepRequest> for SledActor {
+    type Result = ();
+
+    fn handle(&mut self, _: SweepRequest, _: &mut Self::Context) {
+        self.inner.sweep();
+    }
+}
+
+#[cfg(test)]
+mod sled_test {
+    use std::{env, fs, path::PathBuf, process, time::Duration};
+
+    use actix_rt::time::sleep;
+
+    use crate::error::Result;
+    use crate::storage::Storage;
+    use crate::store::{sled::SledActor, StoreLimits, Sweep};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("magnetite_sled_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn test() {
+        let path = temp_path("test");
+        let system = actix_rt::System::new();
+        let store = system.block_on(async { SledActor::open(&path).unwrap().expire(600).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let key = "key";
+            let value = "value".to_string();
+
+            assert!(storage.set(key.as_bytes(), &value).await.is_ok());
+
+            let get_res = storage.get(key).await;
+            assert!(get_res.is_ok());
+            assert_eq!(get_res.unwrap(), Some(value));
+
+            assert!(storage.delete(key.as_bytes()).await.is_ok());
+            let get_res: Result<Option<String>> = storage.get(key).await;
+            assert_eq!(get_res.unwrap(), None);
+        });
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn ttl_test() {
+        let path = temp_path("ttl");
+        let system = actix_rt::System::new();
+        let store = system.block_on(async { SledActor::open(&path).unwrap().expire(600).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            let value = "value".to_string();
+
+            assert!(storage.set_with_ttl("short", &value, 1).await.is_ok());
+            assert!(storage.set_with_ttl("long", &value, 600).await.is_ok());
+            sleep(Duration::from_secs(2)).await;
+
+            let get_res: Result<Option<String>> = storage.get("short").await;
+            assert_eq!(get_res.unwrap(), None);
+            let get_res: Result<Option<String>> = storage.get("long").await;
+            assert_eq!(get_res.unwrap(), Some(value));
+            assert_eq!(storage.metrics().await.unwrap().expired, 1);
+        });
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn lru_test() {
+        let path = temp_path("lru");
+        let system = actix_rt::System::new();
+        let limits = StoreLimits {
+            max_entries: 10,
+            ..Default::default()
+        };
+        let store =
+            system.block_on(async { SledActor::open(&path).unwrap().limits(limits).start(1) });
+        let storage = Storage::new(store);
+
+        system.block_on(async move {
+            for i in 0..10 {
+                assert!(storage.set(format!("key{}", i), &i).await.is_ok());
+            }
+            // key0 becomes the most recently used
+            let get_res: Result<Option<i32>> = storage.get("key0").await;
+            assert_eq!(get_res.unwrap(), Some(0));
+            assert!(storage.set("key10", &10).await.is_ok());
+
+            let get_res: Result<Option<i32>> = storage.get("key0").await;
+            assert_eq!(get_res.unwrap(), Some(0));
+            for key in &["key1", "key2"] {
+                let get_res: Result<Option<i32>> = storage.get(key).await;
+                assert_eq!(get_res.unwrap(), None);
+            }
+            let metrics = storage.metrics().await.unwrap();
+            assert_eq!(metrics.entries, 9);
+            assert_eq!(metrics.evictions, 2);
+        });
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn persist_test() {
+        let path = temp_path("persist");
+        {
+            let store = SledActor::open(&path).unwrap();
+            store.inner.insert(b"key", b"value", 600).unwrap();
+            store.inner.db.flush().unwrap();
+        }
+
+        let store = SledActor::open(&path).unwrap();
+        assert_eq!(
+            store.inner.get(b"key").unwrap().as_deref(),
+            Some(&b"value"[..])
+        );
+        assert_eq!(store.inner.metrics().entries, 1);
+        drop(store);
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn remove_expired_test() {
+        let path = temp_path("remove_expired");
+        let store = SledActor::open(&path).unwrap();
+        let inner = &store.inner;
+        inner.insert(b"key", b"old", -1).unwrap();
+        let expired = inner.data.get(b"key").unwrap().unwrap();
+        // a set lands between reading the expired value and removing it
+        inner.insert(b"key", b"new", 600).unwrap();
+
+        assert!(!inner.remove_expired(b"key", &expired).unwrap());
+        assert_eq!(inner.get(b"key").unwrap().as_deref(), Some(&b"new"[..]));
+        let fresh = inner.data.get(b"key").unwrap().unwrap();
+        inner.insert(b"key", b"old", -1).unwrap();
+        let expired = inner.data.get(b"key").unwrap().unwrap();
+        assert!(!inner.remove_expired(b"key", &fresh).unwrap());
+        assert!(inner.remove_expired(b"key", &expired).unwrap());
+        assert_eq!(inner.metrics().entries, 0);
+        assert_eq!(inner.metrics().bytes, 0);
+        drop(store);
+        let _ = fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn sweep_test() {
+        let path = temp_path("sweep");
+        let store = SledActor::open(&path).unwrap();
+        let inner = &store.inner;
+        inner.insert(b"short", b"value", -1).unwrap();
+        inner.insert(b"long", b"value", 600).unwrap();
+        // rewritten with a longer ttl, so no longer due
+        inner.insert(b"renewed", b"value", -1).unwrap();
+        inner.insert(b"renewed", b"value", 600).unwrap();
+
+        inner.sweep();
+        let metrics = inner.metrics();
+        assert_eq!(metrics.entries, 2);
+        assert_eq!(metrics.expired, 1);
+        assert_eq!(inner.expiry.len(), 2);
+        assert!(inner.get(b"renewed").unwrap().is_some());
+        drop(store);
+        let _ = fs::remove_dir_all(&path);
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use log::warn;
+
+use crate::{
+    error::{Result, StorageError},
+    store::{CacheMetrics, KeyInfo, Store},
+    Key, Value,
+};
+
+/// Bounded in-process store in front of a slower one, e.g. redis.
+///
+/// Reads go to `l1` first and fill it from `l2` on a miss, writes go to both. `l1` entries live
+/// at most `l1_ttl` seconds, so changes made by other instances through `l2` show up after that.
+pub struct TieredStore {
+    l1: Arc<dyn Store>,
+    l2: Arc<dyn Store>,
+    l1_ttl: usize,
+}
+
+impl TieredStore {
+    pub fn new(l1: Arc<dyn Store>, l2: Arc<dyn Store>, l1_ttl: usize) -> Self {
+        TieredStore { l1, l2, l1_ttl }
+    }
+
+    /// Copy a value read from `l2` to `l1`, it expires there no later than in `l2`.
+    async fn fill(&self, key: Key, value: Value) -> Result<()> {
+        let ttl = match self.l2.ttl(key.clone()).await? {
+            Some(ttl) => (ttl as usize).min(self.l1_ttl),
+            None => self.l1_ttl,
+        };
+        self.l1.set_with_ttl(key, value, ttl).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Store for TieredStore {
+    async fn set(&self, key: Key, value: Value) -> Result<()> {
+        self.l2.set(key.clone(), value.clone()).await?;
+        self.l1.set_with_ttl(key, value, self.l1_ttl).await
+    }
+
+    async fn set_with_ttl(&self, key: Key, value: Value, ttl: usize) -> Result<()> {
+        self.l2
+            .set_with_ttl(key.clone(), value.clone(), ttl)
+            .await?;
+        self.l1.set_with_ttl(key, value, ttl.min(self.l1_ttl)).await
+    }
+
+    async fn get(&self, key: Key) -> Result<Option<Value>> {
+        if let Some(value) = self.l1.get(key.clone()).await? {
+            return Ok(Some(value));
+        }
+        let value = match self.l2.get(key.clone()).await? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        // the value is read already, a failed fill only costs another read from `l2`
+        if let Err(err) = self.fill(key, value.clone()).await {
+            warn!(target: "tiered", "failed to fill l1: {}", err);
+        }
+        Ok(Some(value))
+    }
+
+    async fn delete(&self, key: Key) -> Result<()> {
+        self.l2.delete(key.clone()).await?;
+        self.l1.delete(key).await
+    }
+
+    async fn ttl(&self, key: Key) -> Result<Option<i64>> {
+        self.l2.ttl(key).await
+    }
+
+    /// Counters of `l2`, or of `l1` if `l2` keeps none.
+    async fn metrics(&self) -> Result<CacheMetrics> {
+        match self.l2.metrics().await {
+            Err(StorageError::MethodNotSupported) => self.l1.metrics().await,
+            res => res,
+        }
+    }
+
+    /// Keys of `l2`, which holds everything `l1` does.
+    async fn keys(&self, prefix: Key) -> Result<Vec<KeyInfo>> {
+        self.l2.keys(prefix).await
+    }
+
+    async fn delete_prefix(&self, prefix: Key) -> Result<usize> {
+        let deleted = self.l2.delete_prefix(prefix.clone()).await?;
+        self.l1.delete_prefix(prefix).await?;
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tiered_test {
+    use std::{sync::Arc, time::Duration};
+
+    use actix_rt::time::sleep;
+
+    use crate::error::Result;
+    use crate::storage::Storage;
+    use crate::store::{dashmap::DashMapActor, tiered::TieredStore};
+
+    #[test]
+    fn test() {
+        let system = actix_rt::System::new();
+        let (l1, l2) = system.block_on(async {
+            (
+                DashMapActor::new(600).start(1),
+                DashMapActor::new(600).start(1),
+            )
+        });
+        let storage = Storage::new(TieredStore::new(Arc::new(l1), Arc::new(l2.clone()), 1));
+        let l2 = Storage::new(l2);
+
+        system.block_on(async move {
+            let value = "value".to_string();
+
+            // write-through
+            assert!(storage.set("key", &value).await.is_ok());
+            let get_res: Result<Option<String>> = l2.get("key").await;
+            assert_eq!(get_res.unwrap(), Some(value.clone()));
+
+            // served from l1 until its ttl passes
+            assert!(l2.delete("key").await.is_ok());
+            let get_res: Result<Option<String>> = storage.get("key").await;
+            assert_eq!(get_res.unwrap(), Some(value.clone()));
+            sleep(Duration::from_secs(2)).await;
+            let get_res: Result<Option<String>> = storage.get("key").await;
+            assert_eq!(get_res.unwrap(), None);
+
+            // read-through fills l1
+            assert!(l2.set("other", &value).await.is_ok());
+            let get_res: Result<Option<String>> = storage.get("other").await;
+            assert_eq!(get_res.unwrap(), Some(value.clone()));
+            assert!(l2.delete("other").await.is_ok());
+            let get_res: Result<Option<String>> = storage.get("other").await;
+            assert_eq!(get_res.unwrap(), Some(value));
+
+            assert!(storage.delete("other").await.is_ok());
+            let get_res: Result<Option<String>> = storage.get("other").await;
+            assert_eq!(get_res.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn fill_ttl_test() {
+        let system = actix_rt::System::new();
+        let (l1, l2) = system.block_on(async {
+            (
+                DashMapActor::new(600).start(1),
+                DashMapActor::new(600).start(1),
+            )
+        });
+        let storage = Storage::new(TieredStore::new(Arc::new(l1), Arc::new(l2.clone()), 600));
+        let l2 = Storage::new(l2);
+
+        system.block_on(async move {
+            let value = "value".to_string();
+
+            // l1 is filled for what is left of the l2 ttl, not its own
+            assert!(l2.set_with_ttl("key", &value, 1).await.is_ok());
+            let get_res: Result<Option<String>> = storage.get("key").await;
+            assert_eq!(get_res.unwrap(), Some(value));
+            sleep(Duration::from_secs(2)).await;
+            let get_res: Result<Option<String>> = storage.get("key").await;
+            assert_eq!(get_res.unwrap(), None);
+        });
+    }
+}
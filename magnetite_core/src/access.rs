@@ -0,0 +1,253 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    web::{self, Data},
+    Error, HttpRequest,
+};
+use futures::future::{ok, Ready};
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{api::admin::authorize, format::split_suffix, state::AppState, util::constant_time_eq};
+
+/// Header carrying the access key, alternative to `?key=`.
+pub const ACCESS_KEY_HEADER: &str = "X-Access-Key";
+
+/// How `?code=` is derived from the feed path and the access key.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Signature {
+    /// md5 hex of the path followed by the key
+    Md5,
+    /// hmac-sha256 hex of the path, keyed with the key
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+}
+
+impl Default for Signature {
+    fn default() -> Self {
+        Signature::Md5
+    }
+}
+
+/// Access control of every endpoint, disabled without a `key`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// master key, accepted as `?key=` or in the `X-Access-Key` header
+    pub key: Option<String>,
+    pub signature: Signature,
+}
+
+// the config is printed at startup, keep the key out of logs
+impl fmt::Debug for AccessConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessConfig")
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .field("signature", &self.signature)
+            .finish()
+    }
+}
+
+impl AccessConfig {
+    /// Code granting access to the feed at `path` only, in every format and with any filter.
+    ///
+    /// Feeds can be shared with it without handing out the master key. None if access is open.
+    pub fn code(&self, path: &str) -> Option<String> {
+        let key = self.key.as_ref()?;
+        let path = split_suffix(path).0;
+        Some(match self.signature {
+            Signature::Md5 => format!("{:x}", md5::compute(format!("{}{}", path, key))),
+            Signature::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+                    .expect("hmac accepts keys of any length");
+                mac.update(path.as_bytes());
+                format!("{:x}", mac.finalize().into_bytes())
+            },
+        })
+    }
+
+    /// Whether `req` carries the access key, a code for its path or the admin token.
+    fn permits(&self, req: &HttpRequest) -> bool {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return true,
+        };
+        let credentials = web::Query::<Credentials>::from_query(req.query_string())
+            .map(|query| query.into_inner())
+            .unwrap_or_default();
+        let header = req.headers().get(ACCESS_KEY_HEADER).and_then(|value| value.to_str().ok());
+
+        if let Some(given) = credentials.key.as_deref().or(header) {
+            if constant_time_eq(given.as_bytes(), key.as_bytes()) {
+                return true;
+            }
+        }
+        if let Some((given, code)) = credentials.code.zip(self.code(req.path())) {
+            if constant_time_eq(given.as_bytes(), code.as_bytes()) {
+                return true;
+            }
+        }
+        authorize(req).is_ok()
+    }
+}
+
+/// Access credentials, they don't take part in cache keys.
+#[derive(Default, Deserialize)]
+struct Credentials {
+    key: Option<String>,
+    code: Option<String>,
+}
+
+/// `query` without access credentials, for queries kept around after the request.
+pub(crate) fn strip_credentials(query: &str) -> String {
+    let mut pairs = web::Query::<Vec<(String, String)>>::from_query(query)
+        .map(|query| query.into_inner())
+        .unwrap_or_default();
+    pairs.retain(|(name, _)| name != "key" && name != "code");
+    serde_urlencoded::to_string(&pairs).unwrap_or_default()
+}
+
+/// Rejects requests without valid credentials with 403, wrap it outside [`Cache`](crate::Cache)
+/// so they neither get cached feeds nor trigger a scrape.
+pub struct Access;
+
+impl<S, B> Transform<S, ServiceRequest> for Access
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AccessMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct AccessMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let (req, payload) = req.into_parts();
+        let permitted =
+            req.app_data::<Data<AppState>>().map_or(true, |state| state.access.permits(&req));
+        let req = ServiceRequest::from_parts(req, payload);
+
+        Box::pin(async move {
+            if !permitted {
+                return Err(crate::error::Error::Forbidden(req.path().to_string()).into());
+            }
+            svc.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod access_test {
+    use actix_web::test::TestRequest;
+
+    use crate::access::{strip_credentials, AccessConfig, Signature, ACCESS_KEY_HEADER};
+
+    #[test]
+    fn code() {
+        let mut access = AccessConfig {
+            key: Some("secret".to_string()),
+            signature: Signature::Md5,
+        };
+        assert_eq!(
+            access.code("/gcores/news").unwrap(),
+            "3f35a09c3360a90f503bde8f27d4d701"
+        );
+        assert_eq!(
+            access.code("/gcores/news.atom"),
+            access.code("/gcores/news")
+        );
+        access.signature = Signature::HmacSha256;
+        assert_eq!(
+            access.code("/gcores/news").unwrap(),
+            "9579cec97dcf256084b1dbc74ba933a8df24de8d1d10e44a4829cd03191cb172"
+        );
+        assert_eq!(AccessConfig::default().code("/gcores/news"), None);
+    }
+
+    #[test]
+    fn permits() {
+        let access = AccessConfig {
+            key: Some("secret".to_string()),
+            signature: Signature::Md5,
+        };
+        let permits = |req: TestRequest| access.permits(&req.to_http_request());
+
+        assert!(!permits(TestRequest::with_uri("/gcores/news")));
+        assert!(!permits(TestRequest::with_uri("/gcores/news?key=wrong")));
+        assert!(permits(TestRequest::with_uri(
+            "/gcores/news?key=secret&limit=5"
+        )));
+        assert!(permits(
+            TestRequest::with_uri("/gcores/news").insert_header((ACCESS_KEY_HEADER, "secret"))
+        ));
+
+        let code = access.code("/gcores/news").unwrap();
+        assert!(permits(TestRequest::with_uri(&format!(
+            "/gcores/news.atom?code={}",
+            code
+        ))));
+        assert!(!permits(TestRequest::with_uri(&format!(
+            "/gcores/articles?code={}",
+            code
+        ))));
+
+        assert!(AccessConfig::default()
+            .permits(&TestRequest::with_uri("/gcores/news").to_http_request()));
+    }
+
+    #[test]
+    fn strip() {
+        assert_eq!(
+            strip_credentials("limit=5&key=secret&code=abc&format=atom"),
+            "limit=5&format=atom"
+        );
+        assert_eq!(strip_credentials(""), "");
+    }
+
+    #[test]
+    fn redact() {
+        let access = AccessConfig {
+            key: Some("secret".to_string()),
+            signature: Signature::Md5,
+        };
+        assert!(!format!("{:?}", access).contains("secret"));
+    }
+}
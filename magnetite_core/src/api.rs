@@ -0,0 +1,103 @@
+use actix_web::{get, web::Data, HttpResponse};
+use serde::Serialize;
+
+use magnetite_cache::CacheMetrics;
+
+use crate::{
+    http::HostMetrics,
+    route::{Context, Registry, RouteInfo},
+};
+
+pub(crate) mod admin;
+
+/// Query options understood by every route.
+#[derive(Serialize)]
+struct QueryOption {
+    name: &'static str,
+    description: &'static str,
+    example: &'static str,
+}
+
+const QUERY_OPTIONS: &[QueryOption] = &[
+    QueryOption {
+        name: "format",
+        description:
+            "output format: rss, atom or json, a `.atom`/`.json` path suffix works as well",
+        example: "atom",
+    },
+    QueryOption {
+        name: "filter",
+        description: "keep entries whose title or description matches the regex",
+        example: "任天堂|Switch",
+    },
+    QueryOption {
+        name: "filter_title",
+        description: "keep entries whose title matches the regex",
+        example: "评测",
+    },
+    QueryOption {
+        name: "filter_description",
+        description: "keep entries whose description matches the regex",
+        example: "独立游戏",
+    },
+    QueryOption {
+        name: "filterout",
+        description: "drop entries whose title or description matches the regex",
+        example: "广告",
+    },
+    QueryOption {
+        name: "filter_time",
+        description: "keep entries published within the last n seconds",
+        example: "86400",
+    },
+    QueryOption {
+        name: "limit",
+        description: "keep at most n entries",
+        example: "10",
+    },
+    QueryOption {
+        name: "refresh",
+        description: "re-scrape the feed bypassing the cache, requires the admin bearer token",
+        example: "1",
+    },
+    QueryOption {
+        name: "key",
+        description: "access key when access control is enabled, or the `X-Access-Key` header",
+        example: "secret",
+    },
+    QueryOption {
+        name: "code",
+        description: "per-feed access code, see `/api/admin/code`",
+        example: "5ebe2294ecd0e0f08eab7690d2a6ee69",
+    },
+];
+
+#[derive(Serialize)]
+struct Routes {
+    routes: Vec<RouteInfo>,
+    query_options: &'static [QueryOption],
+}
+
+#[get("/api/routes")]
+pub async fn routes(registry: Data<Registry>) -> HttpResponse {
+    HttpResponse::Ok().json(Routes {
+        routes: registry.routes(),
+        query_options: QUERY_OPTIONS,
+    })
+}
+
+#[derive(Serialize)]
+struct Metrics {
+    hosts: Vec<HostMetrics>,
+    /// None for backends without counters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<CacheMetrics>,
+}
+
+#[get("/api/metrics")]
+pub async fn metrics(ctx: Data<Context>) -> HttpResponse {
+    HttpResponse::Ok().json(Metrics {
+        hosts: ctx.client.metrics(),
+        cache: ctx.storage.metrics().await.ok(),
+    })
+}
@@ -0,0 +1,242 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    delete,
+    dev::Payload,
+    get,
+    http::header::AUTHORIZATION,
+    post,
+    web::{Data, Path, Query},
+    FromRequest, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    format::split_suffix,
+    route::{Context, Registry},
+    state::AppState,
+    util::constant_time_eq,
+};
+
+/// Guards the admin endpoints, only extracted from `Authorization: Bearer <admin_token>`.
+pub(crate) struct Admin;
+
+pub(crate) fn authorize(req: &HttpRequest) -> Result<Admin> {
+    let token = req
+        .app_data::<Data<AppState>>()
+        .and_then(|state| state.admin_token.clone())
+        .ok_or_else(|| Error::Unauthorized("admin api is disabled".to_string()))?;
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match bearer {
+        Some(bearer) if constant_time_eq(bearer.as_bytes(), token.as_bytes()) => Ok(Admin),
+        _ => Err(Error::Unauthorized("invalid admin token".to_string())),
+    }
+}
+
+impl FromRequest for Admin {
+    type Config = ();
+    type Error = Error;
+    type Future = Ready<Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authorize(req))
+    }
+}
+
+#[derive(Deserialize)]
+struct PrefixQuery {
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Deserialize)]
+struct KeyQuery {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct PathQuery {
+    path: String,
+}
+
+#[derive(Serialize)]
+struct Code {
+    path: String,
+    code: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Deleted {
+    deleted: usize,
+}
+
+/// Keys starting with `?prefix=`, e.g. `feed:`, with their remaining ttl and size.
+#[get("/api/admin/cache")]
+pub async fn keys(_: Admin, ctx: Data<Context>, query: Query<PrefixQuery>) -> Result<HttpResponse> {
+    let mut keys = ctx.storage.keys(&query.prefix).await?;
+    keys.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// The decoded value at `?key=`, stamped entries include their `fresh_until`.
+#[get("/api/admin/cache/entry")]
+pub async fn entry(_: Admin, ctx: Data<Context>, query: Query<KeyQuery>) -> Result<HttpResponse> {
+    let value: Option<serde_json::Value> = ctx.storage.get(&query.key).await?;
+    Ok(match value {
+        Some(value) => HttpResponse::Ok().json(value),
+        None => HttpResponse::NotFound().finish(),
+    })
+}
+
+#[delete("/api/admin/cache/entry")]
+pub async fn delete_entry(
+    _: Admin, ctx: Data<Context>, query: Query<KeyQuery>,
+) -> Result<HttpResponse> {
+    ctx.storage.delete(&query.key).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Delete the keys starting with `?prefix=`, which must not be empty, see [`flush`].
+#[delete("/api/admin/cache")]
+pub async fn delete_keys(
+    _: Admin, ctx: Data<Context>, query: Query<PrefixQuery>,
+) -> Result<HttpResponse> {
+    if query.prefix.is_empty() {
+        return Err(Error::InvalidQuery(
+            "prefix is required, flush to delete all".to_string(),
+        ));
+    }
+    let deleted = ctx.storage.delete_prefix(&query.prefix).await?;
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+/// Delete every key.
+#[post("/api/admin/cache/flush")]
+pub async fn flush(_: Admin, ctx: Data<Context>) -> Result<HttpResponse> {
+    let deleted = ctx.storage.flush().await?;
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+/// Re-scrape the feed at the rest of the path, e.g. `POST /api/refresh/gcores/news`.
+#[post("/api/refresh/{path:.*}")]
+pub async fn refresh(
+    _: Admin, registry: Data<Registry>, ctx: Data<Context>, path: Path<String>,
+) -> Result<HttpResponse> {
+    let path = format!("/{}", path.into_inner());
+    registry.purge(split_suffix(&path).0, &ctx).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Code granting access to the feed at `?path=` only, see [`AccessConfig::code`].
+///
+/// [`AccessConfig::code`]: crate::access::AccessConfig::code
+#[get("/api/admin/code")]
+pub async fn code(
+    _: Admin, state: Data<AppState>, query: Query<PathQuery>,
+) -> Result<HttpResponse> {
+    let path = split_suffix(&query.path).0.to_string();
+    let code = state
+        .access
+        .code(&path)
+        .ok_or_else(|| Error::InvalidQuery("no access key is configured".to_string()))?;
+    Ok(HttpResponse::Ok().json(Code { path, code }))
+}
+
+#[cfg(test)]
+mod admin_test {
+    use actix_web::{
+        http::{header::AUTHORIZATION, StatusCode},
+        test::{self, TestRequest},
+        web::Data,
+        App,
+    };
+    use magnetite_cache::dashmap_storage;
+
+    use crate::{
+        api,
+        http::{FetchConfig, ProxyConfig},
+        state::AppState,
+    };
+
+    fn state(admin_token: &str) -> AppState {
+        AppState {
+            redis: None,
+            disk: None,
+            cache_expire: 600,
+            route_expire: Default::default(),
+            stale_expire: 0,
+            item_expire: 600,
+            limits: Default::default(),
+            l1_expire: 0,
+            l1_limits: Default::default(),
+            codec: Default::default(),
+            proxy: ProxyConfig::default(),
+            fetch: FetchConfig::default(),
+            admin_token: Some(admin_token.to_string()),
+            access: Default::default(),
+            env: Default::default(),
+        }
+    }
+
+    #[test]
+    fn admin() {
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let state = state("secret");
+            let storage = dashmap_storage(600);
+            let ctx = state.context(storage.clone());
+            let app = test::init_service(
+                App::new()
+                    .app_data(Data::new(state))
+                    .app_data(Data::new(ctx))
+                    .configure(crate::api),
+            )
+            .await;
+            storage.set("feed:/a", &"a".to_string()).await.unwrap();
+            storage.set("feed:/b", &"b".to_string()).await.unwrap();
+            storage.set("render:/a", &"a".to_string()).await.unwrap();
+
+            let req = TestRequest::get().uri("/api/admin/cache").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let req = TestRequest::get()
+                .uri("/api/admin/cache")
+                .insert_header((AUTHORIZATION, "Bearer wrong"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            let admin = |req: TestRequest| req.insert_header((AUTHORIZATION, "Bearer secret"));
+            let req = admin(TestRequest::get().uri("/api/admin/cache?prefix=feed:")).to_request();
+            let keys: Vec<serde_json::Value> = test::read_response_json(&app, req).await;
+            assert_eq!(keys.len(), 2);
+            assert_eq!(keys[0]["key"], "feed:/a");
+
+            let req =
+                admin(TestRequest::get().uri("/api/admin/cache/entry?key=feed:/b")).to_request();
+            let value: String = test::read_response_json(&app, req).await;
+            assert_eq!(value, "b");
+
+            let req =
+                admin(TestRequest::delete().uri("/api/admin/cache?prefix=feed:")).to_request();
+            let deleted: api::admin::Deleted = test::read_response_json(&app, req).await;
+            assert_eq!(deleted.deleted, 2);
+            let req = admin(TestRequest::delete().uri("/api/admin/cache")).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+            let req = TestRequest::post().uri("/api/refresh/gcores/news").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+            let req = admin(TestRequest::post().uri("/api/admin/cache/flush")).to_request();
+            let deleted: api::admin::Deleted = test::read_response_json(&app, req).await;
+            assert_eq!(deleted.deleted, 1);
+        });
+    }
+}
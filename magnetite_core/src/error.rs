@@ -1,6 +1,6 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
-use actix_web::ResponseError;
+use actix_web::{http::StatusCode, ResponseError};
 use log::error;
 use thiserror::Error;
 
@@ -8,15 +8,48 @@ use thiserror::Error;
 pub enum Error {
     #[error("reqwest: {0}")]
     Reqwest(#[from] reqwest::Error),
+    #[error("request timed out: {0}")]
+    Timeout(String),
+    #[error("unexpected status {status}: {url}")]
+    Status { url: String, status: u16 },
+    #[error("response body exceeds {limit} bytes: {url}")]
+    BodyTooLarge { url: String, limit: usize },
     #[error("xml parser: {0}")]
     XmlParseError(#[from] libxml::parser::XmlParseError),
     #[error("cache: {0}")]
     CacheError(#[from] magnetite_cache::error::StorageError),
     #[error("xml operate: {0}")]
     LibXMLError(String),
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("no route matches: {0}")]
+    NotFound(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("access denied: {0}")]
+    Forbidden(String),
+    /// The upstream page is unchanged, see [`Context::upstream`](crate::route::Context::upstream).
+    #[error("not modified: {0}")]
+    NotModified(String),
+    /// Outcome of a fetch shared by coalesced requests.
+    #[error("{0}")]
+    Shared(Arc<Error>),
 }
 
-impl ResponseError for Error {}
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Shared(err) => err.status_code(),
+            Error::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::Status { .. } | Error::BodyTooLarge { .. } => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
 pub type Result<T> = std::result::Result<T, Error>;
 
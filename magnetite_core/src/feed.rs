@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Format-neutral feed produced by site modules, cached as is and rendered on demand.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feed {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub language: Option<String>,
+    pub image: Option<String>,
+    pub authors: Vec<String>,
+    pub updated: DateTime<Utc>,
+    /// seconds the feed is cached, set by the [`Registry`](crate::Registry) when it is fetched
+    #[serde(default)]
+    pub ttl: Option<usize>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub title: String,
+    pub link: String,
+    /// Short summary, plain text or html.
+    pub description: Option<String>,
+    /// Full html content.
+    pub content: Option<String>,
+    pub published: Option<DateTime<Utc>>,
+    pub updated: Option<DateTime<Utc>>,
+    pub authors: Vec<String>,
+    pub categories: Vec<String>,
+    pub enclosures: Vec<Enclosure>,
+    pub guid: String,
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Enclosure {
+    pub url: String,
+    pub mime_type: String,
+    pub length: Option<u64>,
+}
+
+impl Feed {
+    pub fn new(title: String, link: String, entries: Vec<Entry>) -> Self {
+        Feed {
+            title,
+            link,
+            description: "magnetite_rs".to_string(),
+            language: Some("zh-cn".to_string()),
+            image: None,
+            authors: Vec::new(),
+            updated: Utc::now(),
+            ttl: None,
+            entries,
+        }
+    }
+}
+
+impl Entry {
+    /// The link doubles as guid, override it when the source has a stable id.
+    pub fn new(title: String, link: String) -> Self {
+        Entry {
+            title,
+            guid: link.clone(),
+            link,
+            description: None,
+            content: None,
+            published: None,
+            updated: None,
+            authors: Vec::new(),
+            categories: Vec::new(),
+            enclosures: Vec::new(),
+            image: None,
+        }
+    }
+
+    /// Summary if present, full content otherwise.
+    pub fn summary(&self) -> Option<&str> {
+        self.description.as_deref().or_else(|| self.content.as_deref())
+    }
+
+    /// Full content if present, summary otherwise.
+    pub fn html(&self) -> Option<&str> {
+        self.content.as_deref().or_else(|| self.description.as_deref())
+    }
+}
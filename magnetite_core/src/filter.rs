@@ -0,0 +1,162 @@
+use actix_web::web;
+use chrono::{Duration, Utc};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    feed::{Entry, Feed},
+};
+
+/// Names of the query parameters below, they are part of the rendered cache key.
+pub(crate) const QUERY_NAMES: &[&str] = &[
+    "filter",
+    "filter_title",
+    "filter_description",
+    "filterout",
+    "filter_time",
+    "limit",
+];
+
+#[derive(Deserialize)]
+struct FilterQuery {
+    filter: Option<String>,
+    filter_title: Option<String>,
+    filter_description: Option<String>,
+    filterout: Option<String>,
+    filter_time: Option<i64>,
+    limit: Option<usize>,
+}
+
+/// Post-processing applied to the cached feed of any route, driven by query parameters.
+pub struct Filter {
+    /// `filter`: title or description matches
+    include: Option<Regex>,
+    /// `filter_title`: title matches
+    title: Option<Regex>,
+    /// `filter_description`: description matches
+    description: Option<Regex>,
+    /// `filterout`: neither title nor description matches
+    exclude: Option<Regex>,
+    /// `filter_time`: published within the last n seconds
+    max_age: Option<Duration>,
+    /// `limit`: keep the first n entries
+    limit: Option<usize>,
+}
+
+fn regex(pattern: Option<String>) -> Result<Option<Regex>> {
+    pattern
+        .map(|pattern| Regex::new(&pattern).map_err(|err| Error::InvalidQuery(format!("{}", err))))
+        .transpose()
+}
+
+impl Filter {
+    pub fn new(query: &str) -> Result<Self> {
+        let query = web::Query::<FilterQuery>::from_query(query)
+            .map_err(|err| Error::InvalidQuery(format!("{}", err)))?
+            .into_inner();
+
+        Ok(Filter {
+            include: regex(query.filter)?,
+            title: regex(query.filter_title)?,
+            description: regex(query.filter_description)?,
+            exclude: regex(query.filterout)?,
+            max_age: query.filter_time.map(Duration::seconds),
+            limit: query.limit,
+        })
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        let title = entry.title.as_str();
+        let description = entry.summary().unwrap_or_default();
+        let any = |re: &Regex| re.is_match(title) || re.is_match(description);
+
+        self.include.as_ref().map_or(true, any)
+            && self.title.as_ref().map_or(true, |re| re.is_match(title))
+            && self.description.as_ref().map_or(true, |re| re.is_match(description))
+            && !self.exclude.as_ref().map_or(false, any)
+            && self.max_age.map_or(true, |max_age| {
+                // entries without a date can't be judged, keep them
+                entry.published.map_or(true, |published| Utc::now() - published <= max_age)
+            })
+    }
+
+    pub fn apply(&self, mut feed: Feed) -> Feed {
+        feed.entries.retain(|entry| self.matches(entry));
+        if let Some(limit) = self.limit {
+            feed.entries.truncate(limit);
+        }
+        feed
+    }
+}
+
+#[cfg(test)]
+mod filter_test {
+    use actix_web::{http::StatusCode, ResponseError};
+    use chrono::{Duration, Utc};
+
+    use crate::{
+        feed::{Entry, Feed},
+        filter::Filter,
+    };
+
+    fn entry(title: &str, description: &str, age: Option<i64>) -> Entry {
+        let mut entry = Entry::new(title.to_string(), format!("https://example.com/{}", title));
+        entry.description = Some(description.to_string());
+        entry.published = age.map(|age| Utc::now() - Duration::seconds(age));
+        entry
+    }
+
+    fn feed() -> Feed {
+        Feed::new(
+            "news".to_string(),
+            "https://example.com".to_string(),
+            vec![
+                entry("rust 1.52", "release notes", Some(60)),
+                entry("weekly", "this week in rust", Some(3 * 24 * 60 * 60)),
+                entry("podcast", "an interview", Some(120)),
+                entry("undated", "no publish date", None),
+            ],
+        )
+    }
+
+    fn titles(query: &str) -> Vec<String> {
+        let filter = Filter::new(query).unwrap();
+        filter.apply(feed()).entries.into_iter().map(|entry| entry.title).collect()
+    }
+
+    #[test]
+    fn filter() {
+        assert_eq!(titles("").len(), 4);
+        assert_eq!(titles("filter=rust"), vec!["rust 1.52", "weekly"]);
+        assert_eq!(titles("filter_title=rust"), vec!["rust 1.52"]);
+        assert_eq!(titles("filter_description=^this"), vec!["weekly"]);
+        assert_eq!(titles("filterout=rust|date"), vec!["podcast"]);
+        assert_eq!(titles("filter=rust&filterout=week"), vec!["rust 1.52"]);
+    }
+
+    #[test]
+    fn filter_time() {
+        // entries without a date are kept
+        assert_eq!(
+            titles("filter_time=3600"),
+            vec!["rust 1.52", "podcast", "undated"]
+        );
+        assert_eq!(titles("filter_time=90"), vec!["rust 1.52", "undated"]);
+    }
+
+    #[test]
+    fn limit() {
+        assert_eq!(titles("limit=2"), vec!["rust 1.52", "weekly"]);
+        assert_eq!(titles("limit=0"), Vec::<String>::new());
+        assert_eq!(titles("filterout=rust&limit=1"), vec!["podcast"]);
+    }
+
+    #[test]
+    fn invalid() {
+        let err = Filter::new("filter=(").err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        let err = Filter::new("limit=many").err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{future::Future, sync::Arc};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use log::debug;
+use tokio::sync::watch;
+
+use crate::{
+    error::{Error, Result},
+    feed::Feed,
+};
+
+type Outcome = Option<std::result::Result<Feed, Arc<Error>>>;
+
+/// Coalesces concurrent fetches of the same feed into one.
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+    flights: DashMap<String, watch::Receiver<Outcome>>,
+}
+
+/// Forgets the flight once the leading fetch finishes or is dropped.
+struct Landing<'a> {
+    flights: &'a DashMap<String, watch::Receiver<Outcome>>,
+    key: &'a str,
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        self.flights.remove(self.key);
+    }
+}
+
+/// Wait for the leader's result, None if it was dropped before finishing.
+async fn wait(mut rx: watch::Receiver<Outcome>) -> Outcome {
+    loop {
+        let outcome = rx.borrow().clone();
+        if outcome.is_some() {
+            return outcome;
+        }
+        if rx.changed().await.is_err() {
+            return rx.borrow().clone();
+        }
+    }
+}
+
+impl SingleFlight {
+    /// Run `fetch` for `key`, or wait for the result of the fetch already in flight for it.
+    pub async fn run<F, Fut>(&self, key: &str, fetch: F) -> Result<Feed>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Feed>>,
+    {
+        let tx = loop {
+            let rx = match self.flights.entry(key.to_string()) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    let (tx, rx) = watch::channel(None);
+                    entry.insert(rx);
+                    break tx;
+                },
+            };
+            debug!(target: "flight", "waiting for {}", key);
+            if let Some(outcome) = wait(rx).await {
+                return outcome.map_err(Error::Shared);
+            }
+            // the leading request went away, take over
+        };
+
+        let _landing = Landing {
+            flights: &self.flights,
+            key,
+        };
+        let outcome = fetch().await.map_err(Arc::new);
+        let _ = tx.send(Some(outcome.clone()));
+        outcome.map_err(Error::Shared)
+    }
+}
+
+#[cfg(test)]
+mod flight_test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use futures::future::join_all;
+
+    use crate::{error::Error, feed::Feed, flight::SingleFlight};
+
+    #[test]
+    fn coalesce() {
+        let system = actix_rt::System::new();
+        let flights = SingleFlight::default();
+        let fetches = AtomicUsize::new(0);
+
+        system.block_on(async {
+            let fetches = &fetches;
+            let fetch = move || async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                actix_rt::time::sleep(Duration::from_millis(50)).await;
+                Ok(Feed::new(
+                    "news".to_string(),
+                    "https://example.com".to_string(),
+                    vec![],
+                ))
+            };
+            let feeds = join_all((0..8).map(|_| flights.run("feed:/news", fetch))).await;
+            assert_eq!(fetches.load(Ordering::SeqCst), 1);
+            assert!(feeds.iter().all(|feed| feed.as_ref().unwrap().title == "news"));
+
+            // finished flights are forgotten
+            flights.run("feed:/news", fetch).await.unwrap();
+            assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    #[test]
+    fn share_error() {
+        let system = actix_rt::System::new();
+        let flights = SingleFlight::default();
+
+        system.block_on(async {
+            let fetch = || async {
+                actix_rt::time::sleep(Duration::from_millis(50)).await;
+                Err(Error::Timeout("https://example.com".to_string()))
+            };
+            let feeds = join_all((0..4).map(|_| flights.run("feed:/news", fetch))).await;
+            for feed in feeds {
+                match feed {
+                    Err(Error::Shared(err)) => assert!(matches!(*err, Error::Timeout(_))),
+                    _ => panic!("expected the shared timeout"),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,205 @@
+use actix_web::{
+    http::header::{
+        CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    },
+    web, HttpRequest, HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::feed::Feed;
+
+mod atom;
+mod json;
+mod rss;
+
+/// Output formats a feed can be rendered to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rss,
+    Atom,
+    Json,
+}
+
+#[derive(Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rss" | "xml" => Some(Format::Rss),
+            "atom" => Some(Format::Atom),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Rss => "rss",
+            Format::Atom => "atom",
+            Format::Json => "json",
+        }
+    }
+
+    /// `?format=` takes precedence over a path suffix, default is RSS.
+    pub fn new(path: &str, query: &str) -> Self {
+        web::Query::<FormatQuery>::from_query(query)
+            .ok()
+            .and_then(|query| query.format.as_deref().and_then(Format::from_name))
+            .or_else(|| split_suffix(path).1)
+            .unwrap_or(Format::Rss)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Rss => "application/xml",
+            Format::Atom => "application/atom+xml",
+            Format::Json => "application/feed+json",
+        }
+    }
+
+    pub fn render(self, feed: &Feed) -> Rendered {
+        let body = match self {
+            Format::Rss => rss::to_rss(feed).to_string(),
+            Format::Atom => atom::to_atom(feed).to_string(),
+            Format::Json => json::to_json(feed),
+        };
+        Rendered {
+            content_type: self.content_type().to_string(),
+            etag: format!("\"{:x}\"", md5::compute(&body)),
+            last_modified: feed.updated,
+            body,
+        }
+    }
+}
+
+/// A feed rendered to one format, cached per format and filter combination.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rendered {
+    content_type: String,
+    body: String,
+    /// quoted md5 of `body`
+    etag: String,
+    /// when the feed was built
+    last_modified: DateTime<Utc>,
+}
+
+/// IMF-fixdate, the only date format HTTP/1.1 senders may generate.
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+impl Rendered {
+    /// Respond to `req`, with a 304 if the client's copy is current.
+    ///
+    /// `max_age` is the seconds clients may reuse the response without asking again.
+    pub fn response(&self, req: &HttpRequest, max_age: i64) -> HttpResponse {
+        let not_modified = self.not_modified(req);
+        let mut resp = if not_modified {
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+        resp.insert_header((ETAG, self.etag.as_str()))
+            .insert_header((
+                LAST_MODIFIED,
+                self.last_modified.format(HTTP_DATE).to_string(),
+            ))
+            .insert_header((CACHE_CONTROL, format!("max-age={}", max_age.max(0))));
+        if not_modified {
+            resp.finish()
+        } else {
+            resp.insert_header((CONTENT_TYPE, self.content_type.as_str())).body(self.body.clone())
+        }
+    }
+
+    fn not_modified(&self, req: &HttpRequest) -> bool {
+        let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+        // If-Modified-Since is ignored when If-None-Match is present, RFC 7232 section 6
+        if let Some(tags) = header(IF_NONE_MATCH) {
+            return tags.split(',').map(str::trim).any(|tag| {
+                // weak comparison, a weak tag of the same body matches too
+                tag == "*" || tag.trim_start_matches("W/") == self.etag
+            });
+        }
+        header(IF_MODIFIED_SINCE)
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .map_or(false, |since| {
+                self.last_modified.timestamp() <= since.timestamp()
+            })
+    }
+}
+
+/// Split a known format suffix (`.rss`, `.atom`, `.json`) off `path`.
+///
+/// The returned path is what the feed is cached on, so every format shares one entry.
+pub fn split_suffix(path: &str) -> (&str, Option<Format>) {
+    path.rsplit_once('.')
+        .and_then(|(stem, ext)| Format::from_name(ext).map(|format| (stem, Some(format))))
+        .unwrap_or((path, None))
+}
+
+#[cfg(test)]
+mod format_test {
+    use actix_web::{
+        http::{
+            header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+            StatusCode,
+        },
+        test::TestRequest,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::{feed::Feed, format::Format};
+
+    #[test]
+    fn conditional() {
+        let mut feed = Feed::new(
+            "news".to_string(),
+            "https://example.com".to_string(),
+            vec![],
+        );
+        feed.updated = Utc.ymd(2021, 5, 1).and_hms(8, 30, 0);
+        let rendered = Format::Rss.render(&feed);
+
+        let resp = rendered.response(&TestRequest::default().to_http_request(), 300);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(CACHE_CONTROL).unwrap(), "max-age=300");
+        assert_eq!(
+            resp.headers().get(LAST_MODIFIED).unwrap(),
+            "Sat, 01 May 2021 08:30:00 GMT"
+        );
+        let etag = resp.headers().get(ETAG).unwrap().to_str().unwrap().to_string();
+        assert_eq!(etag, Format::Rss.render(&feed).etag);
+        assert_ne!(etag, Format::Atom.render(&feed).etag);
+
+        let status = |name, value: String| {
+            let req = TestRequest::default().insert_header((name, value)).to_http_request();
+            rendered.response(&req, 300).status()
+        };
+        assert_eq!(
+            status(IF_NONE_MATCH, etag.clone()),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status(IF_NONE_MATCH, format!("\"other\", W/{}", etag)),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status(IF_NONE_MATCH, "\"other\"".to_string()),
+            StatusCode::OK
+        );
+
+        let since = |offset: i64| (feed.updated + Duration::seconds(offset)).to_rfc2822();
+        assert_eq!(
+            status(IF_MODIFIED_SINCE, since(0)),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(status(IF_MODIFIED_SINCE, since(-60)), StatusCode::OK);
+        assert_eq!(
+            status(IF_MODIFIED_SINCE, "yesterday".to_string()),
+            StatusCode::OK
+        );
+    }
+}
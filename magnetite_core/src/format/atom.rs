@@ -0,0 +1,139 @@
+use atom_syndication as atom;
+use chrono::{DateTime, FixedOffset};
+
+use crate::feed::{Entry, Feed};
+
+fn person(author: &str) -> atom::Person {
+    let mut person = atom::Person::default();
+    person.set_name(author);
+    person
+}
+
+fn link(href: &str, rel: &str) -> atom::Link {
+    let mut link = atom::Link::default();
+    link.set_href(href);
+    link.set_rel(rel);
+    link
+}
+
+fn to_atom_entry(entry: &Entry, feed_updated: DateTime<FixedOffset>) -> atom::Entry {
+    let published = entry.published.map(Into::into);
+
+    let mut atom_entry = atom::Entry::default();
+    atom_entry.set_title(entry.title.as_str());
+    atom_entry.set_id(entry.guid.as_str());
+    atom_entry.set_updated(entry.updated.map(Into::into).or(published).unwrap_or(feed_updated));
+    atom_entry.set_published(published);
+
+    let mut links = vec![link(&entry.link, "alternate")];
+    links.extend(entry.enclosures.iter().map(|enclosure| {
+        let mut link = link(&enclosure.url, "enclosure");
+        link.set_mime_type(enclosure.mime_type.clone());
+        link.set_length(enclosure.length.map(|length| length.to_string()));
+        link
+    }));
+    atom_entry.set_links(links);
+
+    atom_entry.set_authors(entry.authors.iter().map(|author| person(author)).collect::<Vec<_>>());
+    atom_entry.set_categories(
+        entry
+            .categories
+            .iter()
+            .map(|name| {
+                let mut category = atom::Category::default();
+                category.set_term(name.as_str());
+                category
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    if let Some(description) = &entry.description {
+        atom_entry.set_summary(description.clone());
+    }
+    if let Some(html) = entry.html() {
+        let mut content = atom::Content::default();
+        content.set_content_type("html".to_string());
+        content.set_value(html.to_string());
+        atom_entry.set_content(content);
+    }
+    atom_entry
+}
+
+pub(super) fn to_atom(feed: &Feed) -> atom::Feed {
+    let updated = feed.updated.into();
+
+    let mut atom_feed = atom::Feed::default();
+    atom_feed.set_title(feed.title.as_str());
+    atom_feed.set_id(feed.link.as_str());
+    atom_feed.set_updated(updated);
+    atom_feed.set_links(vec![link(&feed.link, "alternate")]);
+    atom_feed.set_subtitle(feed.description.clone());
+    atom_feed.set_logo(feed.image.clone());
+    // atom requires a feed level author when entries may lack one
+    if feed.authors.is_empty() {
+        atom_feed.set_authors(vec![person(&feed.title)]);
+    } else {
+        atom_feed.set_authors(feed.authors.iter().map(|author| person(author)).collect::<Vec<_>>());
+    }
+    let mut generator = atom::Generator::default();
+    generator.set_value("magnetite_rs");
+    atom_feed.set_generator(generator);
+    atom_feed.set_entries(
+        feed.entries.iter().map(|entry| to_atom_entry(entry, updated)).collect::<Vec<_>>(),
+    );
+    atom_feed
+}
+
+#[cfg(test)]
+mod atom_test {
+    use chrono::{TimeZone, Utc};
+
+    use crate::{
+        feed::{Entry, Feed},
+        format::atom::to_atom,
+    };
+
+    #[test]
+    fn entries() {
+        let mut updated = Entry::new("a".to_string(), "https://example.com/a".to_string());
+        updated.guid = "urn:a".to_string();
+        updated.published = Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0));
+        updated.updated = Some(Utc.ymd(2021, 1, 2).and_hms(0, 0, 0));
+        updated.authors = vec!["alice".to_string()];
+        let mut published = Entry::new("b".to_string(), "https://example.com/b".to_string());
+        published.published = Some(Utc.ymd(2021, 1, 3).and_hms(0, 0, 0));
+        let undated = Entry::new("c".to_string(), "https://example.com/c".to_string());
+        let mut feed = Feed::new(
+            "news".to_string(),
+            "https://example.com".to_string(),
+            vec![updated, published, undated],
+        );
+        feed.updated = Utc.ymd(2021, 1, 4).and_hms(0, 0, 0);
+
+        let atom = to_atom(&feed);
+        // feeds without authors are attributed to their title
+        assert_eq!(atom.authors()[0].name(), "news");
+        let entries = atom.entries();
+        assert_eq!(entries[0].id(), "urn:a");
+        assert_eq!(
+            entries[0].updated().to_rfc3339(),
+            "2021-01-02T00:00:00+00:00"
+        );
+        assert_eq!(entries[0].authors()[0].name(), "alice");
+        // the link doubles as id, the publication date as update
+        assert_eq!(entries[1].id(), "https://example.com/b");
+        assert_eq!(
+            entries[1].updated().to_rfc3339(),
+            "2021-01-03T00:00:00+00:00"
+        );
+        assert_eq!(
+            entries[2].updated().to_rfc3339(),
+            "2021-01-04T00:00:00+00:00"
+        );
+
+        let xml = atom.to_string();
+        assert!(xml.contains("<id>urn:a</id>"));
+        assert!(xml.contains("<updated>2021-01-02T00:00:00+00:00</updated>"));
+        assert!(xml.contains("<author><name>alice</name></author>"));
+    }
+}
@@ -0,0 +1,130 @@
+use serde::Serialize;
+
+use crate::feed::{Entry, Feed};
+
+const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// <https://www.jsonfeed.org/version/1.1/>
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: &'a str,
+    description: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<Author<'a>>,
+    items: Vec<JsonItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonItem<'a> {
+    id: &'a str,
+    url: &'a str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<&'a str>,
+    /// required by the spec when there is no `content_html`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_published: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_modified: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<Author<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Attachment<'a>>,
+}
+
+#[derive(Serialize)]
+struct Author<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct Attachment<'a> {
+    url: &'a str,
+    mime_type: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_in_bytes: Option<u64>,
+}
+
+fn authors(names: &[String]) -> Vec<Author> {
+    names.iter().map(|name| Author { name }).collect()
+}
+
+fn to_json_item(entry: &Entry) -> JsonItem {
+    JsonItem {
+        id: &entry.guid,
+        url: &entry.link,
+        title: &entry.title,
+        content_html: entry.html(),
+        content_text: entry.html().map_or(Some(entry.title.as_str()), |_| None),
+        summary: entry.description.as_deref(),
+        image: entry.image.as_deref(),
+        date_published: entry.published.map(|date| date.to_rfc3339()),
+        date_modified: entry.updated.map(|date| date.to_rfc3339()),
+        authors: authors(&entry.authors),
+        tags: entry.categories.iter().map(String::as_str).collect(),
+        attachments: entry
+            .enclosures
+            .iter()
+            .map(|enclosure| Attachment {
+                url: &enclosure.url,
+                mime_type: &enclosure.mime_type,
+                size_in_bytes: enclosure.length,
+            })
+            .collect(),
+    }
+}
+
+pub(super) fn to_json(feed: &Feed) -> String {
+    let json_feed = JsonFeed {
+        version: VERSION,
+        title: &feed.title,
+        home_page_url: &feed.link,
+        description: &feed.description,
+        icon: feed.image.as_deref(),
+        language: feed.language.as_deref(),
+        authors: authors(&feed.authors),
+        items: feed.entries.iter().map(to_json_item).collect(),
+    };
+    serde_json::to_string(&json_feed).expect("serialize json feed")
+}
+
+#[cfg(test)]
+mod json_test {
+    use crate::{
+        feed::{Entry, Feed},
+        format::json::to_json,
+    };
+
+    #[test]
+    fn content() {
+        let mut with_html = Entry::new("a".to_string(), "https://example.com/a".to_string());
+        with_html.content = Some("<p>a</p>".to_string());
+        let without = Entry::new("b".to_string(), "https://example.com/b".to_string());
+        let feed = Feed::new(
+            "news".to_string(),
+            "https://example.com".to_string(),
+            vec![with_html, without],
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&feed)).unwrap();
+        let items = json["items"].as_array().unwrap();
+        assert_eq!(items[0]["content_html"], "<p>a</p>");
+        assert!(items[0].get("content_text").is_none());
+        assert!(items[1].get("content_html").is_none());
+        assert_eq!(items[1]["content_text"], "b");
+    }
+}
@@ -0,0 +1,69 @@
+use rss::{Category, Channel, ChannelBuilder, Enclosure, Guid, Image, Item, ItemBuilder};
+
+use crate::feed::{Entry, Feed};
+
+fn to_rss_item(entry: &Entry) -> Item {
+    let mut guid = Guid::default();
+    guid.set_permalink(false);
+    guid.set_value(entry.guid.as_str());
+
+    let mut item = ItemBuilder::default()
+        .title(entry.title.clone())
+        .link(entry.link.clone())
+        .description(entry.summary().map(str::to_string))
+        .guid(guid)
+        .pub_date(entry.published.map(|date| date.to_rfc2822()))
+        .categories(
+            entry
+                .categories
+                .iter()
+                .map(|name| {
+                    let mut category = Category::default();
+                    category.set_name(name.as_str());
+                    category
+                })
+                .collect::<Vec<_>>(),
+        )
+        .build()
+        .unwrap();
+
+    // only the first author and enclosure fit into rss
+    if let Some(author) = entry.authors.first() {
+        item.set_author(author.clone());
+    }
+    if let Some(enclosure) = entry.enclosures.first() {
+        let mut rss_enclosure = Enclosure::default();
+        rss_enclosure.set_url(enclosure.url.as_str());
+        rss_enclosure.set_mime_type(enclosure.mime_type.as_str());
+        rss_enclosure.set_length(enclosure.length.unwrap_or_default().to_string());
+        item.set_enclosure(rss_enclosure);
+    }
+    if entry.description.is_some() {
+        item.set_content(entry.content.clone());
+    }
+    item
+}
+
+pub(super) fn to_rss(feed: &Feed) -> Channel {
+    let mut channel = ChannelBuilder::default()
+        .title(feed.title.clone())
+        .link(feed.link.clone())
+        .description(feed.description.clone())
+        .language(feed.language.clone())
+        .generator("magnetite_rs".to_string())
+        // minutes
+        .ttl(feed.ttl.map(|ttl| (ttl / 60).max(1).to_string()))
+        .last_build_date(feed.updated.to_rfc2822())
+        .items(feed.entries.iter().map(to_rss_item).collect::<Vec<_>>())
+        .build()
+        .unwrap();
+
+    if let Some(url) = &feed.image {
+        let mut image = Image::default();
+        image.set_url(url.as_str());
+        image.set_title(feed.title.as_str());
+        image.set_link(feed.link.as_str());
+        channel.set_image(image);
+    }
+    channel
+}
@@ -1,8 +1,453 @@
-use lazy_static::lazy_static;
-use reqwest::Client;
+use std::{collections::HashMap, error::Error as StdError, io, sync::Arc, time::Duration};
+
+use actix_web::rt::time::sleep;
+use log::warn;
+use reqwest::{
+    header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    Client, Proxy, Response, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+pub use limiter::HostMetrics;
+use limiter::{HostLimit, Limiter};
+
+mod limiter;
 
 const UA: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_13_4) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/65.0.3325.181 Safari/537.36";
 
-lazy_static! {
-    pub static ref CLIENT: Client = Client::builder().user_agent(UA).build().unwrap();
+/// Outbound proxy settings, urls may be `http://`, `https://` or `socks5://`.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyConfig {
+    /// Proxy used for every host unless overridden.
+    pub url: Option<String>,
+    /// Hosts connected to directly, `example.com` also covers its subdomains, `*` covers all.
+    pub no_proxy: Vec<String>,
+    /// Per host proxy overrides, keyed like `no_proxy`.
+    pub sites: HashMap<String, String>,
+}
+
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_start_matches('.');
+    pattern == "*"
+        || host == pattern
+        || host.strip_suffix(pattern).map_or(false, |sub| sub.ends_with('.'))
+}
+
+/// Orders host patterns from the least to the most specific, `*` first, then by length.
+fn specificity(pattern: &str) -> (usize, &str) {
+    let pattern = pattern.trim_start_matches('.');
+    (if pattern == "*" { 0 } else { pattern.len() }, pattern)
+}
+
+/// Proxy from the `<scheme>_proxy` or `all_proxy` environment variables, like reqwest does.
+fn system_proxy(scheme: &str) -> Option<Url> {
+    let names = [format!("{}_proxy", scheme), "all_proxy".to_string()];
+    names
+        .iter()
+        .flat_map(|name| vec![name.to_uppercase(), name.clone()])
+        .find_map(|name| std::env::var(name).ok())
+        .and_then(|url| Url::parse(&url).ok())
+}
+
+struct ProxyRules {
+    default: Option<Url>,
+    /// proxies from the environment by scheme, used when `default` is not configured
+    system: Vec<(&'static str, Url)>,
+    no_proxy: Vec<String>,
+    /// most specific pattern first
+    sites: Vec<(String, Url)>,
+}
+
+impl ProxyRules {
+    fn new(config: &ProxyConfig) -> std::result::Result<Self, Box<dyn StdError>> {
+        let mut sites = config
+            .sites
+            .iter()
+            .map(|(host, url)| Ok((host.clone(), Url::parse(url)?)))
+            .collect::<std::result::Result<Vec<_>, Box<dyn StdError>>>()?;
+        sites.sort_by(|(a, _), (b, _)| specificity(b).cmp(&specificity(a)));
+        let system = match config.url {
+            Some(_) => Vec::new(),
+            None => ["http", "https"]
+                .iter()
+                .filter_map(|&scheme| Some((scheme, system_proxy(scheme)?)))
+                .collect(),
+        };
+        Ok(ProxyRules {
+            default: config.url.as_deref().map(Url::parse).transpose()?,
+            system,
+            no_proxy: config.no_proxy.clone(),
+            sites,
+        })
+    }
+
+    fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let host = url.host_str()?;
+        if self.no_proxy.iter().any(|pattern| host_matches(host, pattern)) {
+            return None;
+        }
+        self.sites
+            .iter()
+            .find(|(pattern, _)| host_matches(host, pattern))
+            .map(|(_, proxy)| proxy)
+            .or_else(|| self.default.as_ref())
+            .or_else(|| {
+                self.system
+                    .iter()
+                    .find(|(scheme, _)| *scheme == url.scheme())
+                    .map(|(_, proxy)| proxy)
+            })
+            .cloned()
+    }
+}
+
+/// Timeouts, retries and limits of outbound requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FetchConfig {
+    /// seconds
+    pub connect_timeout: u64,
+    /// seconds, covers the whole request including the body
+    pub timeout: u64,
+    /// retries after the first attempt on 5xx, timeout or connection errors
+    pub retries: u32,
+    /// milliseconds before the first retry, doubled on every further retry
+    pub backoff: u64,
+    /// bytes
+    pub max_body_size: usize,
+    /// requests per second to a single host, 0 for unlimited
+    pub rate_limit: f64,
+    /// requests allowed at once before `rate_limit` kicks in
+    pub burst: u32,
+    /// concurrent requests to a single host, 0 for unlimited
+    pub max_in_flight: usize,
+    /// per host overrides, `example.com` also covers its subdomains
+    pub hosts: HashMap<String, HostConfig>,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            connect_timeout: 10,
+            timeout: 30,
+            retries: 2,
+            backoff: 500,
+            max_body_size: 10 * 1024 * 1024,
+            rate_limit: 2.0,
+            burst: 5,
+            max_in_flight: 4,
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostConfig {
+    pub timeout: Option<u64>,
+    pub retries: Option<u32>,
+    pub max_body_size: Option<usize>,
+    pub rate_limit: Option<f64>,
+    pub burst: Option<u32>,
+    pub max_in_flight: Option<usize>,
+}
+
+/// `FetchConfig` with the host overrides applied.
+struct Limits {
+    host: Option<String>,
+    timeout: Duration,
+    retries: u32,
+    max_body_size: usize,
+    rate: HostLimit,
+}
+
+impl FetchConfig {
+    fn limits(&self, url: &str) -> Limits {
+        let host = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+        let overrides = host
+            .as_ref()
+            .and_then(|host| {
+                self.hosts
+                    .iter()
+                    .filter(|(pattern, _)| host_matches(host, pattern))
+                    .max_by(|(a, _), (b, _)| specificity(a).cmp(&specificity(b)))
+                    .map(|(_, config)| config.clone())
+            })
+            .unwrap_or_default();
+        Limits {
+            host,
+            timeout: Duration::from_secs(overrides.timeout.unwrap_or(self.timeout)),
+            retries: overrides.retries.unwrap_or(self.retries),
+            max_body_size: overrides.max_body_size.unwrap_or(self.max_body_size),
+            rate: HostLimit {
+                rate: overrides.rate_limit.unwrap_or(self.rate_limit),
+                burst: overrides.burst.unwrap_or(self.burst),
+                max_in_flight: overrides.max_in_flight.unwrap_or(self.max_in_flight),
+            },
+        }
+    }
+}
+
+fn is_connection_reset(err: &reqwest::Error) -> bool {
+    let mut source = StdError::source(err);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<io::Error>() {
+            return io_err.kind() == io::ErrorKind::ConnectionReset;
+        }
+        source = err.source();
+    }
+    false
+}
+
+fn is_retryable(err: &Error) -> bool {
+    match err {
+        Error::Timeout(_) => true,
+        Error::Status { status, .. } => *status >= 500,
+        Error::Reqwest(err) => err.is_connect() || is_connection_reset(err),
+        _ => false,
+    }
+}
+
+fn request_error(url: &str, err: reqwest::Error) -> Error {
+    if err.is_timeout() {
+        Error::Timeout(url.to_string())
+    } else {
+        Error::Reqwest(err)
+    }
+}
+
+/// Validators of an earlier response, sent back to ask the server whether it changed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header =
+            |name| headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// HTTP client shared by all routes, built once from the app config.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    config: Arc<FetchConfig>,
+    limiter: Arc<Limiter>,
+}
+
+impl HttpClient {
+    pub fn new(
+        proxy: &ProxyConfig, config: FetchConfig,
+    ) -> std::result::Result<Self, Box<dyn StdError>> {
+        let mut builder = Client::builder()
+            .user_agent(UA)
+            .connect_timeout(Duration::from_secs(config.connect_timeout));
+        // keep reqwest's system proxy detection unless proxying is configured, the rules fall
+        // back to the system proxy themselves so `no_proxy` alone still applies
+        if proxy.url.is_some() || !proxy.sites.is_empty() || !proxy.no_proxy.is_empty() {
+            let rules = ProxyRules::new(proxy)?;
+            builder = builder.proxy(Proxy::custom(move |url| rules.proxy_for(url)));
+        }
+        Ok(HttpClient {
+            client: builder.build()?,
+            config: Arc::new(config),
+            limiter: Default::default(),
+        })
+    }
+
+    /// GET `url` and read the body, retrying with exponential backoff on transient failures.
+    pub async fn bytes(&self, url: &str) -> Result<Vec<u8>> {
+        let fetched = self.bytes_if_modified(url, &Validators::default()).await?;
+        // without validators the server has nothing to answer 304 to
+        Ok(fetched.map(|(body, _)| body).unwrap_or_default())
+    }
+
+    /// Like [`HttpClient::bytes`], but None if `url` is unchanged since the response
+    /// `validators` came from. Otherwise the body comes with its own validators.
+    pub async fn bytes_if_modified(
+        &self, url: &str, validators: &Validators,
+    ) -> Result<Option<(Vec<u8>, Validators)>> {
+        let limits = self.config.limits(url);
+        let mut backoff = Duration::from_millis(self.config.backoff);
+        let mut attempt = 0;
+        loop {
+            match self.fetch_once(url, &limits, validators).await {
+                Err(err) if attempt < limits.retries && is_retryable(&err) => {
+                    attempt += 1;
+                    warn!(target: "http", "{}, retry {} in {:?}", err, attempt, backoff);
+                    sleep(backoff).await;
+                    backoff *= 2;
+                },
+                res => return res,
+            }
+        }
+    }
+
+    /// Like [`HttpClient::bytes`], decoding the body as utf-8.
+    pub async fn text(&self, url: &str) -> Result<String> {
+        let bytes = self.bytes(url).await?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Request counters of every host contacted so far.
+    pub fn metrics(&self) -> Vec<HostMetrics> {
+        self.limiter.metrics()
+    }
+
+    async fn fetch_once(
+        &self, url: &str, limits: &Limits, validators: &Validators,
+    ) -> Result<Option<(Vec<u8>, Validators)>> {
+        let _permit = match &limits.host {
+            Some(host) => Some(self.limiter.acquire(host, &limits.rate).await),
+            None => None,
+        };
+        let mut req = self.client.get(url).timeout(limits.timeout);
+        if let Some(etag) = &validators.etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            req = req.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let resp = req.send().await.map_err(|err| request_error(url, err))?;
+
+        let status = resp.status();
+        if status == StatusCode::NOT_MODIFIED && !validators.is_empty() {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(Error::Status {
+                url: url.to_string(),
+                status: status.as_u16(),
+            });
+        }
+        let validators = Validators::from_headers(resp.headers());
+        let body = read_body(url, resp, limits.max_body_size).await?;
+        Ok(Some((body, validators)))
+    }
+}
+
+async fn read_body(url: &str, mut resp: Response, limit: usize) -> Result<Vec<u8>> {
+    let too_large = || Error::BodyTooLarge {
+        url: url.to_string(),
+        limit,
+    };
+    if resp.content_length().map_or(false, |len| len > limit as u64) {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(|err| request_error(url, err))? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod http_test {
+    use reqwest::Url;
+
+    use crate::http::{host_matches, FetchConfig, HostConfig, ProxyConfig, ProxyRules};
+
+    #[test]
+    fn host_matching() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("www.example.com", "example.com"));
+        assert!(host_matches("www.example.com", ".example.com"));
+        assert!(host_matches("example.com", ".example.com"));
+        assert!(host_matches("anything.org", "*"));
+        assert!(!host_matches("badexample.com", "example.com"));
+        assert!(!host_matches("example.com", "www.example.com"));
+        assert!(!host_matches("example.org", "example.com"));
+    }
+
+    #[test]
+    fn proxy_for() {
+        let config = ProxyConfig {
+            url: Some("http://default:8080".to_string()),
+            no_proxy: vec!["local.example.com".to_string()],
+            sites: vec![
+                ("*", "http://any:8080"),
+                ("example.com", "http://site:8080"),
+                ("cdn.example.com", "socks5://cdn:1080"),
+            ]
+            .into_iter()
+            .map(|(host, url)| (host.to_string(), url.to_string()))
+            .collect(),
+        };
+        let rules = ProxyRules::new(&config).unwrap();
+        let proxy_for = |url: &str| {
+            rules
+                .proxy_for(&Url::parse(url).unwrap())
+                .map(|proxy| proxy.host_str().unwrap().to_string())
+        };
+
+        assert_eq!(
+            proxy_for("https://img.cdn.example.com/a.png").as_deref(),
+            Some("cdn")
+        );
+        assert_eq!(
+            proxy_for("https://cdn.example.com/a.png").as_deref(),
+            Some("cdn")
+        );
+        assert_eq!(
+            proxy_for("https://www.example.com/").as_deref(),
+            Some("site")
+        );
+        assert_eq!(proxy_for("https://example.org/").as_deref(), Some("any"));
+        assert_eq!(proxy_for("https://local.example.com/"), None);
+
+        let rules = ProxyRules::new(&ProxyConfig {
+            url: Some("http://default:8080".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let proxy = rules.proxy_for(&Url::parse("https://example.org/").unwrap());
+        assert_eq!(proxy.unwrap().host_str(), Some("default"));
+
+        let rules = ProxyRules::new(&ProxyConfig {
+            url: Some("http://default:8080".to_string()),
+            no_proxy: vec!["*".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            rules.proxy_for(&Url::parse("https://example.org/").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let host = |retries| HostConfig {
+            retries: Some(retries),
+            ..Default::default()
+        };
+        let mut config = FetchConfig::default();
+        config.hosts.insert("*".to_string(), host(1));
+        config.hosts.insert("example.com".to_string(), host(3));
+        config.hosts.insert("api.example.com".to_string(), host(5));
+
+        assert_eq!(config.limits("https://v1.api.example.com/feed").retries, 5);
+        assert_eq!(config.limits("https://www.example.com/feed").retries, 3);
+        assert_eq!(config.limits("https://example.org/feed").retries, 1);
+        config.hosts.clear();
+        assert_eq!(
+            config.limits("https://example.org/feed").retries,
+            config.retries
+        );
+    }
 }
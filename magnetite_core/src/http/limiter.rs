@@ -0,0 +1,244 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::rt::time::sleep;
+use dashmap::DashMap;
+use log::debug;
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Token bucket, `rate` tokens per second up to `burst`.
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Bucket {
+            rate,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token, or return how long to wait until one is available.
+    fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    requests: AtomicU64,
+    /// currently waiting for an in-flight slot or a token
+    queued: AtomicU64,
+    /// requests that had to wait for an in-flight slot
+    throttled_concurrency: AtomicU64,
+    /// requests that had to wait for a token
+    throttled_rate: AtomicU64,
+}
+
+/// Snapshot of the limiter counters of one host.
+#[derive(Debug, Serialize)]
+pub struct HostMetrics {
+    pub host: String,
+    pub requests: u64,
+    pub queued: u64,
+    pub in_flight: usize,
+    pub throttled_concurrency: u64,
+    pub throttled_rate: u64,
+}
+
+struct Host {
+    /// None: no rate limit
+    bucket: Option<Mutex<Bucket>>,
+    /// None: no in-flight limit
+    slots: Option<(Arc<Semaphore>, usize)>,
+    metrics: Metrics,
+}
+
+/// Held for the duration of a request.
+pub(crate) struct Permit {
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+/// Rate limit settings of a host, 0 disables the respective limit.
+pub(crate) struct HostLimit {
+    pub rate: f64,
+    pub burst: u32,
+    pub max_in_flight: usize,
+}
+
+/// Per host rate and concurrency limits shared by every route.
+#[derive(Default)]
+pub(crate) struct Limiter {
+    hosts: DashMap<String, Arc<Host>>,
+}
+
+impl Limiter {
+    fn host(&self, host: &str, limit: &HostLimit) -> Arc<Host> {
+        self.hosts
+            .entry(host.to_string())
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    bucket: if limit.rate > 0.0 {
+                        Some(Mutex::new(Bucket::new(limit.rate, limit.burst)))
+                    } else {
+                        None
+                    },
+                    slots: if limit.max_in_flight > 0 {
+                        Some((
+                            Arc::new(Semaphore::new(limit.max_in_flight)),
+                            limit.max_in_flight,
+                        ))
+                    } else {
+                        None
+                    },
+                    metrics: Metrics::default(),
+                })
+            })
+            .clone()
+    }
+
+    /// Wait for an in-flight slot and a token of `host`.
+    pub async fn acquire(&self, host: &str, limit: &HostLimit) -> Permit {
+        let state = self.host(host, limit);
+        let metrics = &state.metrics;
+        metrics.requests.fetch_add(1, Ordering::Relaxed);
+        metrics.queued.fetch_add(1, Ordering::Relaxed);
+
+        let slot = match &state.slots {
+            Some((semaphore, _)) => {
+                if semaphore.available_permits() == 0 {
+                    metrics.throttled_concurrency.fetch_add(1, Ordering::Relaxed);
+                    debug!(target: "limiter", "{}: waiting for an in-flight slot", host);
+                }
+                semaphore.clone().acquire_owned().await.ok()
+            },
+            None => None,
+        };
+
+        if let Some(bucket) = &state.bucket {
+            let mut throttled = false;
+            loop {
+                let wait = bucket.lock().unwrap().take();
+                match wait {
+                    Ok(()) => break,
+                    Err(wait) => {
+                        if !throttled {
+                            throttled = true;
+                            metrics.throttled_rate.fetch_add(1, Ordering::Relaxed);
+                        }
+                        debug!(target: "limiter", "{}: rate limited for {:?}", host, wait);
+                        sleep(wait).await;
+                    },
+                }
+            }
+        }
+
+        metrics.queued.fetch_sub(1, Ordering::Relaxed);
+        Permit { _slot: slot }
+    }
+
+    pub fn metrics(&self) -> Vec<HostMetrics> {
+        self.hosts
+            .iter()
+            .map(|entry| {
+                let state = entry.value();
+                let metrics = &state.metrics;
+                HostMetrics {
+                    host: entry.key().clone(),
+                    requests: metrics.requests.load(Ordering::Relaxed),
+                    queued: metrics.queued.load(Ordering::Relaxed),
+                    in_flight: state
+                        .slots
+                        .as_ref()
+                        .map_or(0, |(semaphore, max)| max - semaphore.available_permits()),
+                    throttled_concurrency: metrics.throttled_concurrency.load(Ordering::Relaxed),
+                    throttled_rate: metrics.throttled_rate.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod limiter_test {
+    use std::time::Duration;
+
+    use actix_web::rt::time::timeout;
+
+    use crate::http::limiter::{Bucket, HostLimit, Limiter};
+
+    #[test]
+    fn bucket() {
+        let mut bucket = Bucket::new(2.0, 3);
+        // a full burst is available at once
+        for _ in 0..3 {
+            assert!(bucket.take().is_ok());
+        }
+        let wait = bucket.take().unwrap_err();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+
+        // one second refills `rate` tokens
+        bucket.last -= Duration::from_secs(1);
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_ok());
+        assert!(bucket.take().is_err());
+
+        // but never more than `burst`
+        bucket.last -= Duration::from_secs(10);
+        for _ in 0..3 {
+            assert!(bucket.take().is_ok());
+        }
+        assert!(bucket.take().is_err());
+    }
+
+    #[test]
+    fn max_in_flight() {
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let limiter = Limiter::default();
+            let limit = HostLimit {
+                rate: 0.0,
+                burst: 0,
+                max_in_flight: 2,
+            };
+            let wait = Duration::from_millis(50);
+
+            let first = limiter.acquire("example.com", &limit).await;
+            let _second = limiter.acquire("example.com", &limit).await;
+            assert!(timeout(wait, limiter.acquire("example.com", &limit)).await.is_err());
+            // other hosts have their own slots
+            assert!(timeout(wait, limiter.acquire("example.org", &limit)).await.is_ok());
+
+            let metrics = limiter.metrics();
+            let host = metrics.iter().find(|host| host.host == "example.com").unwrap();
+            assert_eq!(host.in_flight, 2);
+            assert_eq!(host.throttled_concurrency, 1);
+
+            drop(first);
+            assert!(timeout(wait, limiter.acquire("example.com", &limit)).await.is_ok());
+        });
+    }
+}
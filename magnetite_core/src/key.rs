@@ -0,0 +1,108 @@
+use actix_web::{web, HttpRequest};
+
+use crate::{
+    filter::QUERY_NAMES,
+    format::{split_suffix, Format},
+};
+
+/// Cache keys of a feed request.
+#[derive(Debug)]
+pub struct CacheKey {
+    /// The unfiltered feed as scraped, shared by every format and filter of a route + params.
+    pub feed: String,
+    /// The rendered body of one format and filter combination.
+    pub rendered: String,
+    /// Validators of the upstream pages the feed was built from.
+    pub validators: String,
+}
+
+impl CacheKey {
+    pub fn new(path: &str, query: &str) -> Self {
+        let format = Format::new(path, query);
+        let path = split_suffix(path).0;
+
+        // only options that change the body take part, sorted so their order doesn't matter
+        let mut pairs = web::Query::<Vec<(String, String)>>::from_query(query)
+            .map(|query| query.into_inner())
+            .unwrap_or_default();
+        pairs.retain(|(name, value)| !value.is_empty() && QUERY_NAMES.contains(&name.as_str()));
+        pairs.push(("format".to_string(), format.name().to_string()));
+        pairs.sort();
+        let query = serde_urlencoded::to_string(&pairs).unwrap_or_default();
+
+        CacheKey {
+            feed: format!("feed:{}", path),
+            rendered: format!("render:{}?{}", path, query),
+            validators: format!("validators:{}", path),
+        }
+    }
+
+    pub fn from_request(req: &HttpRequest) -> Self {
+        CacheKey::new(req.path(), req.query_string())
+    }
+
+    /// Prefix of every rendered variant of the feed at `path`.
+    pub fn rendered_prefix(path: &str) -> String {
+        format!("render:{}?", split_suffix(path).0)
+    }
+
+    /// Full text of the article at `url`, kept across refreshes of the feeds listing it.
+    pub fn item(url: &str) -> String {
+        format!("item:{}", url)
+    }
+}
+
+#[cfg(test)]
+mod key_test {
+    use magnetite_cache::dashmap_storage;
+
+    use crate::key::CacheKey;
+
+    #[test]
+    fn normalize() {
+        let atom = CacheKey::new("/gcores/news", "limit=5&format=atom&utm_source=x");
+        let suffix = CacheKey::new("/gcores/news.atom", "limit=5");
+        assert_eq!(atom.feed, suffix.feed);
+        assert_eq!(atom.rendered, suffix.rendered);
+
+        let ordered = CacheKey::new("/gcores/news", "filter=a&limit=5");
+        let reversed = CacheKey::new("/gcores/news", "limit=5&filter=a");
+        assert_eq!(ordered.rendered, reversed.rendered);
+
+        let plain = CacheKey::new("/gcores/news", "");
+        assert_eq!(plain.feed, atom.feed);
+        assert_ne!(plain.rendered, atom.rendered);
+        assert_ne!(plain.rendered, ordered.rendered);
+        assert_ne!(plain.feed, CacheKey::new("/gcores/articles", "").feed);
+        assert!(atom.rendered.starts_with(&CacheKey::rendered_prefix("/gcores/news.atom")));
+        assert!(!CacheKey::new("/gcores/news2", "")
+            .rendered
+            .starts_with(&CacheKey::rendered_prefix("/gcores/news")));
+    }
+
+    #[test]
+    fn dashmap() {
+        let system = actix_rt::System::new();
+        let storage = system.block_on(async { dashmap_storage(600) });
+
+        system.block_on(async move {
+            let plain = CacheKey::new("/gcores/news", "");
+            let limited = CacheKey::new("/gcores/news", "limit=5");
+            let atom = CacheKey::new("/gcores/news.atom", "");
+
+            assert!(storage.set(&plain.feed, &"feed".to_string()).await.is_ok());
+            assert!(storage.set(&plain.rendered, &"plain".to_string()).await.is_ok());
+            assert!(storage.set(&limited.rendered, &"limited".to_string()).await.is_ok());
+
+            let get = |key: String| {
+                let storage = storage.clone();
+                async move { storage.get::<_, String>(key).await.unwrap() }
+            };
+            assert_eq!(get(plain.rendered).await, Some("plain".to_string()));
+            assert_eq!(get(limited.rendered).await, Some("limited".to_string()));
+            assert_eq!(get(atom.rendered).await, None);
+            assert_eq!(get(atom.feed).await, Some("feed".to_string()));
+            assert_eq!(get(limited.feed).await, Some("feed".to_string()));
+        });
+    }
+}
@@ -1,17 +1,38 @@
-use actix_web::{web, Scope};
+use actix_web::web::ServiceConfig;
 
-use http::CLIENT;
+pub use access::Access;
 pub use middleware::Cache;
-use sites::gcores;
+pub use route::Registry;
 
+pub mod access;
+mod api;
 mod error;
-mod http;
+mod feed;
+mod filter;
+mod flight;
+mod format;
+pub mod http;
+mod key;
 mod middleware;
+pub mod route;
 mod sites;
 pub mod state;
 mod util;
 mod xpath;
 
-pub fn scope() -> Scope {
-    web::scope("/").service(gcores::gcores_handle)
+pub fn registry() -> Registry {
+    sites::registry()
+}
+
+/// Mount the `/api` endpoints, they expect a `Data<Registry>` and `Data<AppState>` in app data.
+pub fn api(cfg: &mut ServiceConfig) {
+    cfg.service(api::routes)
+        .service(api::metrics)
+        .service(api::admin::keys)
+        .service(api::admin::entry)
+        .service(api::admin::delete_entry)
+        .service(api::admin::delete_keys)
+        .service(api::admin::flush)
+        .service(api::admin::refresh)
+        .service(api::admin::code);
 }
@@ -8,17 +8,38 @@ use std::{
 
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    http,
-    web::Data,
-    Error, HttpResponse,
+    http::StatusCode,
+    web::{self, Data},
+    Error, HttpRequest, HttpResponse,
 };
+use chrono::Utc;
 use futures::future::{ok, Ready};
-use rss::Channel;
+use log::{debug, warn};
+use serde::Deserialize;
 
-use magnetite_cache::Storage;
+use crate::{
+    api::admin::authorize,
+    feed::Feed,
+    format::{split_suffix, Rendered},
+    key::CacheKey,
+    route::{render, respond, Context as RouteContext, Registry},
+};
 
 pub struct Cache;
 
+#[derive(Deserialize)]
+struct RefreshQuery {
+    refresh: Option<String>,
+}
+
+/// `?refresh=1`, an admin asking to re-scrape the feed instead of serving the cached one.
+fn wants_refresh(query: &str) -> bool {
+    web::Query::<RefreshQuery>::from_query(query)
+        .ok()
+        .and_then(|query| query.into_inner().refresh)
+        .map_or(false, |refresh| refresh == "1" || refresh == "true")
+}
+
 impl<S, B> Transform<S, ServiceRequest> for Cache
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
@@ -59,20 +80,79 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
 
-        let key = req.path().to_string();
-        let cache = req.app_data::<Data<Storage>>().unwrap().clone();
+        let registry = req.app_data::<Data<Registry>>().cloned();
+        let ctx = req.app_data::<Data<RouteContext>>().unwrap().clone();
+        let (req, payload) = req.into_parts();
+        let key = CacheKey::from_request(&req);
+        let refresh = wants_refresh(req.query_string());
 
         Box::pin(async move {
-            if let Ok(Some(channel)) = cache.get::<_, Channel>(&key).await {
-                Ok(req.into_response(
-                    HttpResponse::Ok()
-                        .append_header((http::header::CONTENT_TYPE, "application/xml"))
-                        .body(channel.to_string())
-                        .into_body(),
-                ))
-            } else {
-                Ok(svc.call(req).await?)
+            if refresh {
+                authorize(&req)?;
+                if let Some(registry) = &registry {
+                    registry.purge(split_suffix(req.path()).0, &ctx).await?;
+                }
+            }
+            let path = req.path().to_string();
+            let query = req.query_string().to_string();
+            let resp = match cached(&req, &key, registry.clone(), &ctx).await? {
+                Some(resp) => ServiceResponse::new(req, resp.into_body()),
+                None => svc.call(ServiceRequest::from_parts(req, payload)).await?,
+            };
+            // only feeds that could be fetched and rendered are kept warm
+            let status = resp.status();
+            if status.is_success() || status == StatusCode::NOT_MODIFIED {
+                if let Some(registry) = &registry {
+                    registry.touch(&path, &query);
+                }
             }
+            Ok(resp)
         })
     }
 }
+
+/// Respond from the cache, None if the feed isn't cached.
+async fn cached(
+    req: &HttpRequest, key: &CacheKey, registry: Option<Data<Registry>>, ctx: &Data<RouteContext>,
+) -> crate::error::Result<Option<HttpResponse>> {
+    let storage = &ctx.storage;
+    if let Ok(Some(rendered)) = storage.get_stamped::<_, Rendered>(&key.rendered).await {
+        if !rendered.stale {
+            // clients may reuse it for as long as it stays fresh here
+            let max_age = rendered.fresh_until - Utc::now().timestamp();
+            return Ok(Some(rendered.value.response(req, max_age)));
+        }
+    }
+    let feed = match storage.get_stamped::<_, Feed>(&key.feed).await {
+        Ok(Some(feed)) => feed,
+        _ => return Ok(None),
+    };
+    let resp = if feed.stale {
+        revalidate(registry, req.path(), ctx.clone());
+        // not cached, the refresh renders this variant once the feed is fresh
+        render(req.path(), req.query_string(), feed.value)?.response(req, 0)
+    } else {
+        // a variant the refresh didn't keep warm, e.g. past the variants remembered
+        respond(req, key, feed.value, feed.fresh_until, ctx).await?
+    };
+    Ok(Some(resp))
+}
+
+/// Refresh a stale feed in the background, the stale copy keeps being served until it succeeds.
+fn revalidate(registry: Option<Data<Registry>>, path: &str, ctx: Data<RouteContext>) {
+    let registry = match registry {
+        Some(registry) => registry,
+        None => return,
+    };
+    let path = split_suffix(path).0.to_string();
+    if registry.backing_off(&path) {
+        debug!(target: "cache", "{} is stale, its last refresh failed recently", path);
+        return;
+    }
+    debug!(target: "cache", "{} is stale, refreshing", path);
+    actix_web::rt::spawn(async move {
+        if let Err(err) = registry.refresh(&path, &ctx).await {
+            warn!(target: "cache", "refresh {} failed, serving stale: {}", path, err);
+        }
+    });
+}
@@ -0,0 +1,864 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    dev::{Path, ResourceDef},
+    web::{self, Data, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use chrono::Utc;
+use dashmap::DashMap;
+use log::{debug, warn};
+use serde::{de::IgnoredAny, Serialize};
+
+use magnetite_cache::Storage;
+
+use crate::{
+    access::strip_credentials,
+    error::{Error, Result},
+    feed::{Entry, Feed},
+    filter::Filter,
+    flight::SingleFlight,
+    format::{split_suffix, Format, Rendered},
+    http::{HttpClient, Validators},
+    key::CacheKey,
+};
+
+/// Path parameters of a matched route, e.g. `category` for `/gcores/{category}`.
+pub type Params = HashMap<String, String>;
+
+/// Describes a route parameter.
+#[derive(Serialize)]
+pub struct Param {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub example: &'static str,
+}
+
+/// Static description of a route.
+#[derive(Serialize)]
+pub struct Meta {
+    pub name: &'static str,
+    /// Home page of the source site.
+    pub site: &'static str,
+    pub maintainer: &'static str,
+    pub params: Vec<Param>,
+    /// Seconds the feed is cached, None for the configured `cache_expire`.
+    pub ttl: Option<usize>,
+}
+
+/// Shared resources available to routes while fetching.
+#[derive(Clone)]
+pub struct Context {
+    pub storage: Storage,
+    pub client: HttpClient,
+    /// seconds a feed is served from the cache before it is refreshed
+    pub cache_expire: usize,
+    /// per route `cache_expire` overrides, keyed by route pattern or feed path
+    pub route_expire: HashMap<String, usize>,
+    /// seconds a stale feed keeps being served past `cache_expire` while refreshes fail
+    pub stale_expire: usize,
+    /// seconds an entry stays in the item cache
+    pub item_expire: usize,
+    /// set while the registry fetches a feed
+    pub(crate) validated: Option<Arc<Validated>>,
+}
+
+/// Upstream validators of the feed being fetched, keyed by url.
+pub(crate) struct Validated {
+    /// from the fetch the cached feed was built by
+    previous: HashMap<String, Validators>,
+    /// collected by this fetch, saved along with the feed
+    current: Mutex<HashMap<String, Validators>>,
+    /// set by [`Context::degrade`]
+    degraded: AtomicBool,
+}
+
+impl Context {
+    /// Look `url` up in the item cache, falling back to `fetch` and caching its result.
+    ///
+    /// Routes use it for full text articles, which rarely change once published, so only
+    /// new entries are fetched when the feed itself expires.
+    pub async fn cached_entry<F, Fut>(&self, url: &str, fetch: F) -> Result<Entry>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Entry>>,
+    {
+        let key = CacheKey::item(url);
+        if let Ok(Some(entry)) = self.storage.get(&key).await {
+            return Ok(entry);
+        }
+
+        let entry = fetch().await?;
+        // the entry is fetched already, a cache failure only costs a refetch next time
+        if let Err(err) = self.storage.set_with_ttl(&key, &entry, self.item_expire).await {
+            warn!(target: "route", "failed to cache {}: {}", key, err);
+        }
+        Ok(entry)
+    }
+
+    /// GET a page the whole feed is built from, e.g. the channel listing.
+    ///
+    /// The request is conditional on the validators of the page from the last fetch. If the
+    /// page is unchanged this fails with [`Error::NotModified`] and the registry keeps the
+    /// cached feed, so routes simply propagate it.
+    pub async fn upstream(&self, url: &str) -> Result<Vec<u8>> {
+        let validated = match &self.validated {
+            Some(validated) => validated,
+            None => return self.client.bytes(url).await,
+        };
+        let previous = validated.previous.get(url).cloned().unwrap_or_default();
+        match self.client.bytes_if_modified(url, &previous).await? {
+            Some((body, validators)) => {
+                validated.current.lock().unwrap().insert(url.to_string(), validators);
+                Ok(body)
+            },
+            None => Err(Error::NotModified(url.to_string())),
+        }
+    }
+
+    /// Mark the feed being fetched as incomplete, e.g. an entry lacks its full text.
+    ///
+    /// Its validators are not saved, so the next fetch isn't answered with the incomplete feed
+    /// while the listing stays unchanged.
+    pub fn degrade(&self) {
+        if let Some(validated) = &self.validated {
+            validated.degraded.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// A copy of this context collecting upstream validators, see [`Context::upstream`].
+    fn validate(&self, previous: HashMap<String, Validators>) -> Context {
+        Context {
+            validated: Some(Arc::new(Validated {
+                previous,
+                current: Default::default(),
+                degraded: AtomicBool::new(false),
+            })),
+            ..self.clone()
+        }
+    }
+
+    /// Validators collected by [`Context::upstream`] so far, none once degraded.
+    fn validators(&self) -> HashMap<String, Validators> {
+        self.validated
+            .as_ref()
+            .filter(|validated| !validated.degraded.load(Ordering::Relaxed))
+            .map(|validated| validated.current.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Seconds the feed at `path` stays fresh: config by path, then by pattern, then route meta.
+    fn expire(&self, entry: &RouteEntry, path: &str) -> usize {
+        self.route_expire
+            .get(path)
+            .or_else(|| self.route_expire.get(entry.route.path()))
+            .copied()
+            .or(entry.route.meta().ttl)
+            .unwrap_or(self.cache_expire)
+    }
+
+    /// Cache a feed fresh for `ttl` seconds, it is served stale for `stale_expire` past that.
+    async fn store_feed(&self, key: &str, feed: &Feed, ttl: usize) -> Result<()> {
+        self.storage.set_stamped(key, feed, ttl, ttl + self.stale_expire).await?;
+        Ok(())
+    }
+
+    /// Cache a rendered feed for `ttl` seconds, past that it is rendered from the stale feed.
+    async fn store_rendered(&self, key: &str, rendered: &Rendered, ttl: usize) -> Result<()> {
+        self.storage.set_stamped(key, rendered, ttl, ttl).await?;
+        Ok(())
+    }
+}
+
+/// A feed source. Implementors only scrape, caching and rendering are done by the [`Registry`].
+#[async_trait::async_trait(?Send)]
+pub trait Route: Send + Sync {
+    /// actix path pattern, e.g. `/gcores/{category}`.
+    fn path(&self) -> &'static str;
+
+    fn meta(&self) -> Meta;
+
+    async fn fetch(&self, params: &Params, ctx: &Context) -> Result<Feed>;
+}
+
+struct RouteEntry {
+    def: ResourceDef,
+    route: Box<dyn Route>,
+}
+
+impl RouteEntry {
+    fn params(&self, path: &str) -> Option<Params> {
+        let mut path = Path::new(path.to_string());
+        if self.def.match_path(&mut path) {
+            Some(path.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect())
+        } else {
+            None
+        }
+    }
+}
+
+/// Public description of a registered route, served by `/api/routes`.
+#[derive(Serialize)]
+pub struct RouteInfo {
+    pub path: &'static str,
+    /// `path` with every parameter replaced by its example.
+    pub example: String,
+    #[serde(flatten)]
+    pub meta: Meta,
+}
+
+// rendered variants remembered per feed, beyond that new ones are not refreshed
+const MAX_VARIANTS: usize = 32;
+// feeds remembered, any path matching a route pattern is recorded
+const MAX_HOT: usize = 1024;
+// a stale feed isn't refreshed on request again within this long after a failed refresh
+const REFRESH_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A recently requested feed.
+struct Hot {
+    last_request: Instant,
+    /// rendered key -> (request path, query) of every variant requested
+    variants: HashMap<String, (String, String)>,
+}
+
+/// All routes served by the app.
+#[derive(Default)]
+pub struct Registry {
+    routes: Vec<Arc<RouteEntry>>,
+    /// feed path (without format suffix) -> recent requests
+    hot: DashMap<String, Hot>,
+    /// feed path -> last failed refresh
+    failures: DashMap<String, Instant>,
+    flights: Arc<SingleFlight>,
+}
+
+impl Registry {
+    pub fn register<R: Route + 'static>(mut self, route: R) -> Self {
+        self.routes.push(Arc::new(RouteEntry {
+            def: ResourceDef::new(route.path()),
+            route: Box::new(route),
+        }));
+        self
+    }
+
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes
+            .iter()
+            .map(|entry| {
+                let meta = entry.route.meta();
+                let path = entry.route.path();
+                let example = meta.params.iter().fold(path.to_string(), |path, param| {
+                    path.replace(&format!("{{{}}}", param.name), param.example)
+                });
+                RouteInfo {
+                    path,
+                    example,
+                    meta,
+                }
+            })
+            .collect()
+    }
+
+    fn resolve(&self, path: &str) -> Option<(Arc<RouteEntry>, Params)> {
+        self.routes
+            .iter()
+            .find_map(|entry| entry.params(path).map(|params| (entry.clone(), params)))
+    }
+
+    /// Record a request so its feed and rendered variant are kept warm by [`Registry::refresh`].
+    pub(crate) fn touch(&self, path: &str, query: &str) {
+        let feed_path = split_suffix(path).0;
+        if self.resolve(feed_path).is_none() {
+            return;
+        }
+
+        if self.hot.len() >= MAX_HOT && !self.hot.contains_key(feed_path) {
+            let oldest =
+                self.hot.iter().min_by_key(|hot| hot.last_request).map(|hot| hot.key().clone());
+            if let Some(oldest) = oldest {
+                self.hot.remove(&oldest);
+            }
+        }
+
+        let query = strip_credentials(query);
+        let key = CacheKey::new(path, &query);
+        let mut hot = self.hot.entry(feed_path.to_string()).or_insert_with(|| Hot {
+            last_request: Instant::now(),
+            variants: HashMap::new(),
+        });
+        hot.last_request = Instant::now();
+        if hot.variants.len() < MAX_VARIANTS || hot.variants.contains_key(&key.rendered) {
+            hot.variants.insert(key.rendered, (path.to_string(), query));
+        }
+    }
+
+    /// Seconds the shortest lived feed stays fresh, over the configured and route defined ttls.
+    pub fn shortest_expire(&self, ctx: &Context) -> usize {
+        self.routes
+            .iter()
+            .filter_map(|entry| entry.route.meta().ttl)
+            .chain(ctx.route_expire.values().copied())
+            .fold(ctx.cache_expire, usize::min)
+    }
+
+    /// Feed paths requested within `window`, older ones are forgotten.
+    pub fn hot(&self, window: Duration) -> Vec<String> {
+        self.hot.retain(|_, hot| hot.last_request.elapsed() <= window);
+        self.hot.iter().map(|hot| hot.key().clone()).collect()
+    }
+
+    /// Whether the cached feed at `path` is missing or turns stale within `within`.
+    pub async fn due(&self, path: &str, ctx: &Context, within: Duration) -> bool {
+        let key = CacheKey::new(path, "").feed;
+        match ctx.storage.get_stamped::<_, IgnoredAny>(&key).await {
+            Ok(Some(lookup)) => {
+                lookup.fresh_until <= Utc::now().timestamp() + within.as_secs() as i64
+            },
+            _ => true,
+        }
+    }
+
+    /// Whether the last refresh of the feed at `path` failed within [`REFRESH_BACKOFF`].
+    pub(crate) fn backing_off(&self, path: &str) -> bool {
+        self.failures.get(path).map_or(false, |failed| failed.elapsed() < REFRESH_BACKOFF)
+    }
+
+    /// Fetch the feed at `path` and overwrite its cache entry and recently requested variants.
+    pub async fn refresh(&self, path: &str, ctx: &Context) -> Result<()> {
+        let res = self.refresh_variants(path, ctx).await;
+        if res.is_ok() {
+            self.failures.remove(path);
+        } else {
+            self.failures.retain(|_, failed| failed.elapsed() < REFRESH_BACKOFF);
+            self.failures.insert(path.to_string(), Instant::now());
+        }
+        res
+    }
+
+    async fn refresh_variants(&self, path: &str, ctx: &Context) -> Result<()> {
+        let (entry, params) =
+            self.resolve(path).ok_or_else(|| Error::NotFound(path.to_string()))?;
+        let feed = fetch(&self.flights, &entry, path, &params, ctx).await?;
+
+        let variants = self
+            .hot
+            .get(path)
+            .map(|hot| hot.variants.clone().into_iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let ttl = feed.ttl.unwrap_or(ctx.cache_expire);
+        for (key, (variant, query)) in variants {
+            match render(&variant, &query, feed.clone()) {
+                Ok(rendered) => ctx.store_rendered(&key, &rendered, ttl).await?,
+                // e.g. an invalid filter, which fails the request as well
+                Err(err) => {
+                    warn!(target: "route", "forgetting variant {}: {}", key, err);
+                    if let Some(mut hot) = self.hot.get_mut(path) {
+                        hot.variants.remove(&key);
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop everything cached for the feed at `path` and fetch it again, so a fixed route shows
+    /// its new output at once: rendered variants, full text of its entries and upstream
+    /// validators, which would otherwise let an unchanged page keep the old feed.
+    pub async fn purge(&self, path: &str, ctx: &Context) -> Result<()> {
+        let key = CacheKey::new(path, "");
+        if let Ok(Some(cached)) = ctx.storage.get_stamped::<_, Feed>(&key.feed).await {
+            for entry in cached.value.entries {
+                ctx.storage.delete(CacheKey::item(&entry.link)).await?;
+            }
+        }
+        ctx.storage.delete(&key.validators).await?;
+        ctx.storage.delete_prefix(CacheKey::rendered_prefix(path)).await?;
+        debug!(target: "route", "purged {}, refreshing", path);
+        self.refresh(path, ctx).await
+    }
+
+    /// Mount every registered route, use with `App::configure`.
+    pub fn configure(&self, cfg: &mut ServiceConfig) {
+        for entry in self.routes.iter() {
+            let entry = entry.clone();
+            let flights = self.flights.clone();
+            cfg.route(
+                entry.route.path(),
+                web::get().to(move |req: HttpRequest, ctx: Data<Context>| {
+                    handle(entry.clone(), flights.clone(), req, ctx)
+                }),
+            );
+        }
+    }
+}
+
+/// Fetch and cache the feed at `path`, concurrent fetches of the same feed share one scrape.
+async fn fetch(
+    flights: &SingleFlight, entry: &RouteEntry, path: &str, params: &Params, ctx: &Context,
+) -> Result<Feed> {
+    let key = CacheKey::new(path, "");
+    flights
+        .run(&key.feed, || async {
+            let previous: HashMap<String, Validators> =
+                ctx.storage.get(&key.validators).await.ok().flatten().unwrap_or_default();
+            let scoped = ctx.validate(previous.clone());
+            let (mut feed, validators) = match entry.route.fetch(params, &scoped).await {
+                Err(Error::NotModified(url)) => {
+                    match ctx.storage.get_stamped::<_, Feed>(&key.feed).await {
+                        Ok(Some(cached)) => {
+                            debug!(target: "route", "{} not modified, keeping {}", url, path);
+                            (cached.value, previous)
+                        },
+                        // the feed went before its validators, fetch it in full
+                        _ => {
+                            let scoped = ctx.validate(HashMap::new());
+                            let feed = entry.route.fetch(params, &scoped).await?;
+                            (feed, scoped.validators())
+                        },
+                    }
+                },
+                res => (res?, scoped.validators()),
+            };
+            let ttl = ctx.expire(entry, path);
+            feed.ttl = Some(ttl);
+            ctx.store_feed(&key.feed, &feed, ttl).await?;
+            if validators.is_empty() {
+                // e.g. degraded, the next fetch must not keep this feed on a 304
+                ctx.storage.delete(&key.validators).await?;
+            } else {
+                let ttl = ttl + ctx.stale_expire;
+                ctx.storage.set_with_ttl(&key.validators, &validators, ttl).await?;
+            }
+            Ok(feed)
+        })
+        .await
+}
+
+async fn handle(
+    entry: Arc<RouteEntry>, flights: Arc<SingleFlight>, req: HttpRequest, ctx: Data<Context>,
+) -> Result<HttpResponse> {
+    // `/gcores/news.atom` is fetched as `/gcores/news`
+    let path = split_suffix(req.path()).0;
+    // `/gcores/.atom` matches the route, but `/gcores/` doesn't
+    let params = entry.params(path).ok_or_else(|| Error::NotFound(path.to_string()))?;
+    debug!(target: "route", "path: {}, params: {:?}", path, params);
+
+    let feed = fetch(&flights, &entry, path, &params, &ctx).await?;
+    let fresh_until = Utc::now().timestamp() + feed.ttl.unwrap_or(ctx.cache_expire) as i64;
+    respond(&req, &CacheKey::from_request(&req), feed, fresh_until, &ctx).await
+}
+
+/// Apply the query's filters to the unfiltered `feed` and render it in the requested format.
+pub(crate) fn render(path: &str, query: &str, feed: Feed) -> Result<Rendered> {
+    let feed = Filter::new(query)?.apply(feed);
+    Ok(Format::new(path, query).render(&feed))
+}
+
+/// Render `feed` for `req` and cache the result under the rendered key.
+///
+/// The rendered feed turns stale along with `feed`, at the utc timestamp `fresh_until`.
+pub(crate) async fn respond(
+    req: &HttpRequest, key: &CacheKey, feed: Feed, fresh_until: i64, ctx: &Context,
+) -> Result<HttpResponse> {
+    let rendered = render(req.path(), req.query_string(), feed)?;
+    let ttl = fresh_until - Utc::now().timestamp();
+    if ttl > 0 {
+        ctx.store_rendered(&key.rendered, &rendered, ttl as usize).await?;
+    }
+    Ok(rendered.response(req, ttl))
+}
+
+#[cfg(test)]
+mod route_test {
+    use std::{
+        collections::HashMap,
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use std::cell::Cell;
+
+    use actix_web::{
+        dev::Service,
+        http::{header::AUTHORIZATION, StatusCode},
+        test::{self, TestRequest},
+        web::Data,
+        App,
+    };
+    use magnetite_cache::{dashmap_storage, Storage};
+
+    use crate::{
+        error::Result,
+        feed::{Entry, Feed},
+        http::{FetchConfig, HttpClient, ProxyConfig, Validators},
+        key::CacheKey,
+        middleware::Cache,
+        route::{Context, Meta, Params, Registry, Route},
+        state::AppState,
+    };
+
+    /// Serves a listing with etag `"v1"`, 304 to requests carrying it. Returns its url and the
+    /// heads of the requests received.
+    fn upstream() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/listing", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => head.extend_from_slice(&buf[..len]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&head).to_lowercase();
+                let resp = if head.contains("if-none-match: \"v1\"") {
+                    "HTTP/1.1 304 Not Modified\r\netag: \"v1\"\r\nconnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\netag: \"v1\"\r\ncontent-length: 7\r\n\
+                     connection: close\r\n\r\nlisting"
+                };
+                let _ = stream.write_all(resp.as_bytes());
+                received.lock().unwrap().push(head);
+            }
+        });
+        (url, requests)
+    }
+
+    fn context(storage: Storage) -> Context {
+        let proxy = ProxyConfig {
+            no_proxy: vec!["*".to_string()],
+            ..Default::default()
+        };
+        Context {
+            storage,
+            client: HttpClient::new(&proxy, FetchConfig::default()).unwrap(),
+            cache_expire: 600,
+            route_expire: HashMap::new(),
+            stale_expire: 600,
+            item_expire: 600,
+            validated: None,
+        }
+    }
+
+    /// A feed titled after the upstream listing.
+    struct Listing {
+        url: String,
+        degraded: bool,
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl Route for Listing {
+        fn path(&self) -> &'static str {
+            "/listing"
+        }
+
+        fn meta(&self) -> Meta {
+            Meta {
+                name: "listing",
+                site: "",
+                maintainer: "",
+                params: Vec::new(),
+                ttl: None,
+            }
+        }
+
+        async fn fetch(&self, _: &Params, ctx: &Context) -> Result<Feed> {
+            let body = ctx.upstream(&self.url).await?;
+            if self.degraded {
+                ctx.degrade();
+            }
+            Ok(Feed::new(
+                String::from_utf8(body).unwrap(),
+                self.url.clone(),
+                Vec::new(),
+            ))
+        }
+    }
+
+    /// An empty feed titled after its category, fetched without any request.
+    struct Category;
+
+    #[async_trait::async_trait(?Send)]
+    impl Route for Category {
+        fn path(&self) -> &'static str {
+            "/category/{category}"
+        }
+
+        fn meta(&self) -> Meta {
+            Meta {
+                name: "category",
+                site: "",
+                maintainer: "",
+                params: Vec::new(),
+                ttl: Some(300),
+            }
+        }
+
+        async fn fetch(&self, params: &Params, _: &Context) -> Result<Feed> {
+            Ok(Feed::new(
+                params["category"].clone(),
+                String::new(),
+                Vec::new(),
+            ))
+        }
+    }
+
+    #[test]
+    fn not_modified() {
+        let (url, requests) = upstream();
+        let registry = Registry::default().register(Listing {
+            url: url.clone(),
+            degraded: false,
+        });
+        let key = CacheKey::new("/listing", "");
+
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let ctx = context(dashmap_storage(600));
+            registry.refresh("/listing", &ctx).await.unwrap();
+            let validators: Option<HashMap<String, Validators>> =
+                ctx.storage.get(&key.validators).await.unwrap();
+            assert_eq!(validators.unwrap()[&url].etag.as_deref(), Some("\"v1\""));
+
+            // stale, so the refresh has to be answered from it
+            let cached = Feed::new("cached".to_string(), url.clone(), Vec::new());
+            ctx.storage.set_stamped(&key.feed, &cached, 0, 600).await.unwrap();
+            registry.refresh("/listing", &ctx).await.unwrap();
+
+            let lookup = ctx.storage.get_stamped::<_, Feed>(&key.feed).await.unwrap().unwrap();
+            assert_eq!(lookup.value.title, "cached");
+            assert!(!lookup.stale);
+        });
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+    }
+
+    #[test]
+    fn degraded() {
+        let (url, requests) = upstream();
+        let registry = Registry::default().register(Listing {
+            url,
+            degraded: true,
+        });
+        let key = CacheKey::new("/listing", "");
+
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let ctx = context(dashmap_storage(600));
+            registry.refresh("/listing", &ctx).await.unwrap();
+            let validators: Option<HashMap<String, Validators>> =
+                ctx.storage.get(&key.validators).await.unwrap();
+            assert!(validators.is_none());
+
+            registry.refresh("/listing", &ctx).await.unwrap();
+            let lookup = ctx.storage.get_stamped::<_, Feed>(&key.feed).await.unwrap().unwrap();
+            assert_eq!(lookup.value.title, "listing");
+        });
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[1].contains("if-none-match"));
+    }
+
+    #[test]
+    fn bare_suffix() {
+        let registry = Registry::default().register(Category);
+
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let ctx = context(dashmap_storage(600));
+            let app = test::init_service(
+                App::new().app_data(Data::new(ctx)).configure(|cfg| registry.configure(cfg)),
+            )
+            .await;
+
+            let req = TestRequest::get().uri("/category/news.atom").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            // matches the route pattern, but the feed path `/category/` doesn't
+            let req = TestRequest::get().uri("/category/.atom").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        });
+    }
+
+    #[test]
+    fn expire() {
+        let registry = Registry::default().register(Category).register(Listing {
+            url: String::new(),
+            degraded: false,
+        });
+        let (category, _) = registry.resolve("/category/news").unwrap();
+        let (listing, _) = registry.resolve("/listing").unwrap();
+
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let mut ctx = context(dashmap_storage(600));
+
+            assert_eq!(ctx.expire(&listing, "/listing"), 600);
+            assert_eq!(ctx.expire(&category, "/category/news"), 300);
+            ctx.route_expire.insert("/category/{category}".to_string(), 120);
+            assert_eq!(ctx.expire(&category, "/category/news"), 120);
+            ctx.route_expire.insert("/category/news".to_string(), 60);
+            assert_eq!(ctx.expire(&category, "/category/news"), 60);
+            assert_eq!(ctx.expire(&category, "/category/reviews"), 120);
+        });
+    }
+
+    #[test]
+    fn cached_entry() {
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let ctx = context(dashmap_storage(600));
+            let fetched = Cell::new(0);
+            let fetch = || async {
+                fetched.set(fetched.get() + 1);
+                Ok(Entry::new(
+                    "article".to_string(),
+                    "http://example.com/1".to_string(),
+                ))
+            };
+
+            let entry = ctx.cached_entry("http://example.com/1", fetch).await.unwrap();
+            assert_eq!(entry.title, "article");
+            let entry = ctx.cached_entry("http://example.com/1", fetch).await.unwrap();
+            assert_eq!(entry.title, "article");
+            assert_eq!(fetched.get(), 1);
+            assert!(ctx
+                .storage
+                .get::<_, Entry>(CacheKey::item("http://example.com/1"))
+                .await
+                .unwrap()
+                .is_some());
+        });
+    }
+
+    #[test]
+    fn purge() {
+        let (url, requests) = upstream();
+        let registry = Registry::default().register(Listing {
+            url: url.clone(),
+            degraded: false,
+        });
+        let key = CacheKey::new("/listing", "");
+        let rendered = CacheKey::new("/listing.atom", "limit=1").rendered;
+        let article = "http://example.com/1";
+
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let ctx = context(dashmap_storage(600));
+            let mut cached = Feed::new("cached".to_string(), url.clone(), Vec::new());
+            cached.entries.push(Entry::new("article".to_string(), article.to_string()));
+            ctx.storage.set_stamped(&key.feed, &cached, 600, 600).await.unwrap();
+            let validators: HashMap<_, _> = vec![(
+                url.clone(),
+                Validators {
+                    etag: Some("\"v1\"".to_string()),
+                    last_modified: None,
+                },
+            )]
+            .into_iter()
+            .collect();
+            ctx.storage.set(&key.validators, &validators).await.unwrap();
+            ctx.storage.set(CacheKey::item(article), &"full text".to_string()).await.unwrap();
+            ctx.storage.set(&rendered, &"rendered".to_string()).await.unwrap();
+            ctx.storage.set("render:/listings?format=rss", &"other".to_string()).await.unwrap();
+
+            registry.purge("/listing", &ctx).await.unwrap();
+            let item: Option<String> = ctx.storage.get(CacheKey::item(article)).await.unwrap();
+            assert!(item.is_none());
+            let variant: Option<String> = ctx.storage.get(&rendered).await.unwrap();
+            assert!(variant.is_none());
+            let other: Option<String> =
+                ctx.storage.get("render:/listings?format=rss").await.unwrap();
+            assert!(other.is_some());
+            // fetched in full rather than kept on a 304
+            let lookup = ctx.storage.get_stamped::<_, Feed>(&key.feed).await.unwrap().unwrap();
+            assert_eq!(lookup.value.title, "listing");
+        });
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].contains("if-none-match"));
+    }
+
+    #[test]
+    fn refresh_query() {
+        let (url, requests) = upstream();
+        let registry = Data::new(Registry::default().register(Listing {
+            url,
+            degraded: false,
+        }));
+        let state = AppState {
+            redis: None,
+            disk: None,
+            cache_expire: 600,
+            route_expire: Default::default(),
+            stale_expire: 600,
+            item_expire: 600,
+            limits: Default::default(),
+            l1_expire: 0,
+            l1_limits: Default::default(),
+            codec: Default::default(),
+            proxy: ProxyConfig::default(),
+            fetch: FetchConfig::default(),
+            admin_token: Some("secret".to_string()),
+            access: Default::default(),
+            env: Default::default(),
+        };
+
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let ctx = context(dashmap_storage(600));
+            let app = test::init_service(
+                App::new()
+                    .app_data(Data::new(state))
+                    .app_data(Data::new(ctx))
+                    .app_data(registry.clone())
+                    .wrap(Cache)
+                    .configure(|cfg| registry.configure(cfg)),
+            )
+            .await;
+
+            let req = TestRequest::get().uri("/listing").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            // served from the cache
+            let req = TestRequest::get().uri("/listing").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(requests.lock().unwrap().len(), 1);
+
+            let req = TestRequest::get().uri("/listing?refresh=1").to_request();
+            let err = app.call(req).await.err().unwrap();
+            assert_eq!(
+                err.as_response_error().status_code(),
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(requests.lock().unwrap().len(), 1);
+
+            let req = TestRequest::get()
+                .uri("/listing?refresh=1")
+                .insert_header((AUTHORIZATION, "Bearer secret"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 2);
+            assert!(!requests[1].contains("if-none-match"));
+        });
+    }
+}
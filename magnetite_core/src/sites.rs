@@ -1,32 +1,13 @@
-use chrono::Utc;
-use rss::{Channel, ChannelBuilder, Guid, Item, ItemBuilder};
+use crate::{feed::Entry, route::Registry};
 
 pub mod gcores;
 
-fn channel(title: String, url: String, items: Vec<Item>) -> Channel {
-    ChannelBuilder::default()
-        .title(title)
-        .link(url)
-        .description("magnetite_rs")
-        .language("zh-cn".to_string())
-        .generator("magnetite_rs".to_string())
-        .ttl("5".to_string())
-        .last_build_date(Utc::now().to_rfc2822())
-        .items(items)
-        .build()
-        .unwrap()
+pub(crate) fn registry() -> Registry {
+    Registry::default().register(gcores::Gcores)
 }
 
-fn item(title: String, link: String, desc: String) -> Item {
-    let mut guid = Guid::default();
-    guid.set_permalink(false);
-    guid.set_value(&link);
-
-    ItemBuilder::default()
-        .title(title)
-        .link(link)
-        .description(format!("{}", desc))
-        .guid(guid)
-        .build()
-        .unwrap()
+fn entry(title: String, link: String, content: String) -> Entry {
+    let mut entry = Entry::new(title, link);
+    entry.content = Some(content);
+    entry
 }
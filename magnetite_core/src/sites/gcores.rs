@@ -1,19 +1,18 @@
-use actix_web::web::Data;
-use actix_web::{get, http, web, HttpResponse};
-use log::{debug, error, info};
-use rss::{Channel, Item};
-
-use magnetite_cache::Storage;
+use futures::{stream, StreamExt};
+use log::{debug, error, info, warn};
 
 use crate::{
     error::{Error, Result},
-    sites::{channel, item},
+    feed::{Entry, Feed},
+    route::{Context, Meta, Param, Params, Route},
+    sites::entry,
     util::ajson_get,
     xpath::Document,
-    CLIENT,
 };
 
 const BASE_URL: &str = "https://www.gcores.com";
+// articles fetched at once per channel
+const FAN_OUT: usize = 4;
 
 fn get_images_info(json: &str) -> Option<Vec<(String, String)>> {
     let entity_map = ajson::get(&json, "data.attributes.content")
@@ -47,13 +46,13 @@ fn get_images_info(json: &str) -> Option<Vec<(String, String)>> {
         .flatten()
 }
 
-async fn get_item(url: String, title: String) -> Result<Item> {
+async fn get_item(ctx: &Context, url: String, title: String) -> Result<Entry> {
     let item_url = format!("{}{}", BASE_URL, url);
     debug!(target: "get_item", "item_url: {}", item_url);
 
     let api_url = format!("https://www.gcores.com/gapi/v1{}?include=media", url);
-    let gapi_resp = CLIENT.get(&api_url).send().await?.text().await?;
-    let article_resp = CLIENT.get(&item_url).send().await?.bytes().await?;
+    let gapi_resp = ctx.client.text(&api_url).await?;
+    let article_resp = ctx.client.bytes(&item_url).await?;
 
     let doc = Document::from_bytes(article_resp)?;
 
@@ -98,12 +97,12 @@ async fn get_item(url: String, title: String) -> Result<Item> {
         })
         .transpose()?;
 
-    Ok(item(title, item_url, doc.node_to_string(&content[0])))
+    Ok(entry(title, item_url, doc.node_to_string(&content[0])))
 }
 
-async fn get_channel(url: &str) -> Result<Channel> {
+async fn get_channel(ctx: &Context, url: &str) -> Result<Feed> {
     debug!(target: "get_channel", "url: {}", url);
-    let resp = CLIENT.get(url).send().await?.bytes().await?;
+    let resp = ctx.upstream(url).await?;
 
     let doc = Document::from_bytes(resp)?;
 
@@ -116,7 +115,7 @@ async fn get_channel(url: &str) -> Result<Channel> {
     let item_node =
         doc.evaluate("//div[contains(@class,'original-normal') and contains(@class,'am_card')]")?;
 
-    let mut items = Vec::new();
+    let mut cards = Vec::new();
     for node in item_node.iter() {
         let url = node
             .find_nodes(".//a[@class='original_imgArea_cover']/@href")?
@@ -129,28 +128,162 @@ async fn get_channel(url: &str) -> Result<Channel> {
             .map(|node| node.content())
             .ok_or(Error::LibXMLError("get item title failed".to_string()))?;
 
-        let item = get_item(url, title).await?;
-        items.push(item);
+        cards.push((url, title));
+    }
+
+    let items = stream::iter(cards)
+        .map(|(url, title)| async move {
+            let item_url = format!("{}{}", BASE_URL, url);
+            let item =
+                ctx.cached_entry(&item_url, || get_item(ctx, url.clone(), title.clone())).await;
+            match item {
+                Ok(item) => item,
+                Err(err) => {
+                    // keep the item without full text rather than failing the whole feed
+                    warn!(target: "get_channel", "get item {} failed: {}", url, err);
+                    ctx.degrade();
+                    Entry::new(title, item_url)
+                },
+            }
+        })
+        .buffered(FAN_OUT)
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(Feed::new(title, url.to_owned(), items))
+}
+
+pub struct Gcores;
+
+#[async_trait::async_trait(?Send)]
+impl Route for Gcores {
+    fn path(&self) -> &'static str {
+        "/gcores/{category}"
+    }
+
+    fn meta(&self) -> Meta {
+        Meta {
+            name: "机核",
+            site: BASE_URL,
+            maintainer: "simoin",
+            params: vec![Param {
+                name: "category",
+                description: "分类, 如 news, articles, videos",
+                example: "news",
+            }],
+            ttl: None,
+        }
     }
 
-    Ok(channel(title, url.to_owned(), items))
+    async fn fetch(&self, params: &Params, ctx: &Context) -> Result<Feed> {
+        let category = params
+            .get("category")
+            .ok_or_else(|| Error::NotFound("missing category".to_string()))?;
+        let url = format!("{}/{}", BASE_URL, category);
+        get_channel(ctx, &url).await
+    }
 }
 
-#[get("/gcores/{category}")]
-pub async fn gcores_handle(
-    category: web::Path<(String,)>,
-    storage: Data<Storage>,
-) -> Result<HttpResponse> {
-    debug!(target: "gcores_handle", "category: {:?}", category);
-    let category = category.into_inner().0;
-    let url = format!("{}/{}", BASE_URL, &category);
-    let key = format!("/gcores/{}", &category);
-
-    let channel = get_channel(&url).await?;
-    storage.set(&key, &channel).await?;
-
-    Ok(HttpResponse::Ok()
-        .append_header((http::header::CONTENT_TYPE, "application/xml"))
-        .body(channel.to_string()))
-    // channel.to_string().with_status(StatusCode::OK).with_header((http::header::CONTENT_TYPE, "application/xml"))
+#[cfg(test)]
+mod gcores_test {
+    use std::{
+        collections::HashMap,
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use magnetite_cache::dashmap_storage;
+
+    use crate::{
+        http::{FetchConfig, HttpClient, ProxyConfig},
+        key::CacheKey,
+        route::Context,
+        sites::{entry, gcores::get_channel},
+    };
+
+    const CHANNEL: &str = r#"<html><head><title>news</title></head><body>
+        <div class="original-normal am_card">
+            <a class="original_imgArea_cover" href="/articles/1"></a>
+            <a class="am_card_content original_content"><h3>one</h3></a>
+        </div>
+        <div class="original-normal am_card">
+            <a class="original_imgArea_cover" href="/articles/2"></a>
+            <a class="am_card_content original_content"><h3>two</h3></a>
+        </div>
+        <div class="original-normal am_card">
+            <a class="original_imgArea_cover" href="/articles/3"></a>
+            <a class="am_card_content original_content"><h3>three</h3></a>
+        </div>
+    </body></html>"#;
+
+    /// Serves the channel listing to every request, returns its url.
+    fn serve_channel() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/news", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => head.extend_from_slice(&buf[..len]),
+                    }
+                }
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    CHANNEL.len(),
+                    CHANNEL
+                );
+                let _ = stream.write_all(resp.as_bytes());
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn failed_item() {
+        // gcores itself is unreachable, articles are fetched through a proxy refusing them
+        let proxy = ProxyConfig {
+            no_proxy: vec!["127.0.0.1".to_string()],
+            sites: vec![("gcores.com".to_string(), "http://127.0.0.1:1".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let fetch = FetchConfig {
+            retries: 0,
+            ..Default::default()
+        };
+        let url = serve_channel();
+
+        let system = actix_rt::System::new();
+        system.block_on(async {
+            let ctx = Context {
+                storage: dashmap_storage(600),
+                client: HttpClient::new(&proxy, fetch).unwrap(),
+                cache_expire: 600,
+                route_expire: HashMap::new(),
+                stale_expire: 0,
+                item_expire: 600,
+                validated: None,
+            };
+            // full text of the first and last article is cached already
+            for (id, title) in &[(1, "one"), (3, "three")] {
+                let link = format!("https://www.gcores.com/articles/{}", id);
+                let cached = entry(title.to_string(), link.clone(), "<p>text</p>".to_string());
+                ctx.storage.set(CacheKey::item(&link), &cached).await.unwrap();
+            }
+
+            let feed = get_channel(&ctx, &url).await.unwrap();
+            let titles = feed.entries.iter().map(|entry| entry.title.as_str()).collect::<Vec<_>>();
+            assert_eq!(titles, vec!["one", "two", "three"]);
+            assert!(feed.entries[0].content.is_some());
+            // degraded to the listing's title and link
+            assert_eq!(feed.entries[1].link, "https://www.gcores.com/articles/2");
+            assert!(feed.entries[1].content.is_none());
+            assert!(feed.entries[2].content.is_some());
+        });
+    }
 }
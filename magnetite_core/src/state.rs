@@ -1,19 +1,67 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
+pub use magnetite_cache::{Codec, Compression, Encoding, StoreLimits};
 use magnetite_cache::*;
 
+use crate::{
+    access::AccessConfig,
+    http::{FetchConfig, HttpClient, ProxyConfig},
+    route::Context,
+};
+
 pub struct AppState {
     pub redis: Option<String>,
+    /// sled database directory, used when `redis` is None
+    pub disk: Option<PathBuf>,
     pub cache_expire: usize,
+    pub route_expire: HashMap<String, usize>,
+    pub stale_expire: usize,
+    pub item_expire: usize,
+    /// bounds of the memory and disk caches, unused with redis
+    pub limits: StoreLimits,
+    /// seconds entries live in the in-process cache in front of redis or disk, 0 disables it
+    pub l1_expire: usize,
+    pub l1_limits: StoreLimits,
+    pub codec: Codec,
+    pub proxy: ProxyConfig,
+    pub fetch: FetchConfig,
+    /// bearer token of the `/api/admin` endpoints, None disables them
+    pub admin_token: Option<String>,
+    pub access: AccessConfig,
     pub env: HashMap<String, String>,
 }
 
 impl AppState {
     pub async fn storage(&self) -> Storage {
-        if let Some(redis_url) = &self.redis {
+        let storage = if let Some(redis_url) = &self.redis {
             redis_storage(redis_url.parse().unwrap(), self.cache_expire).await
+        } else if let Some(path) = &self.disk {
+            sled_storage(path, self.cache_expire, self.limits).expect("open disk cache")
+        } else {
+            return bounded_dashmap_storage(self.cache_expire, self.limits).codec(self.codec);
+        };
+
+        let storage = storage.codec(self.codec);
+        if self.l1_expire > 0 {
+            tiered_storage(storage, self.l1_expire, self.l1_limits)
         } else {
-            dashmap_storage(self.cache_expire)
+            storage
+        }
+    }
+
+    pub fn client(&self) -> HttpClient {
+        HttpClient::new(&self.proxy, self.fetch.clone()).expect("invalid proxy config")
+    }
+
+    pub fn context(&self, storage: Storage) -> Context {
+        Context {
+            storage,
+            client: self.client(),
+            cache_expire: self.cache_expire,
+            route_expire: self.route_expire.clone(),
+            stale_expire: self.stale_expire,
+            item_expire: self.item_expire,
+            validated: None,
         }
     }
 }
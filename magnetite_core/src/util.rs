@@ -1,3 +1,8 @@
 pub fn ajson_get(json: &str, xpath: &str) -> Option<String> {
     ajson::get(json, xpath).map(|val| val.to_string())
 }
+
+/// Compare without returning early, so secrets can't be guessed byte by byte from timings.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}